@@ -0,0 +1,317 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::blocking::{Client, ClientBuilder, Request, Response};
+use reqwest::redirect::Policy;
+use reqwest::{NoProxy, Proxy, StatusCode, Url};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Redirects reqwest follows when the request does not say otherwise.
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    // seconds to wait for the connection to be established
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<f64>,
+    // seconds for the whole request, from connecting to reading the body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    // 0 turns redirect following off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    // http://, https://, socks5:// or socks5h:// url of the proxy
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // hosts, domains or CIDR blocks that bypass the proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<Vec<String>>,
+}
+
+impl ClientConfig {
+    /// Values that must never be printed: the proxy password, given on its
+    /// own or in the proxy url.
+    pub fn secrets(&self) -> Vec<String> {
+        let Some(p) = &self.proxy else {
+            return Vec::new();
+        };
+        let url_password = Url::parse(&p.url)
+            .ok()
+            .and_then(|u| u.password().map(str::to_string));
+        p.password.iter().cloned().chain(url_password).collect()
+    }
+
+    /// Apply the timeouts and proxy to the client. Redirects are configured
+    /// separately by `HttpClient::build`, which needs to observe them.
+    pub fn configure(&self, mut client_builder: ClientBuilder) -> anyhow::Result<ClientBuilder> {
+        if let Some(t) = self.connect_timeout {
+            client_builder = client_builder.connect_timeout(seconds(t, "connect_timeout")?);
+        }
+        if let Some(t) = self.timeout {
+            client_builder = client_builder.timeout(seconds(t, "timeout")?);
+        }
+        if let Some(p) = &self.proxy {
+            // An explicit proxy also stops reqwest from reading the
+            // HTTP_PROXY/HTTPS_PROXY environment variables
+            let mut proxy =
+                Proxy::all(&p.url).with_context(|| format!("invalid proxy url={}", p.url))?;
+            match (&p.username, &p.password) {
+                (Some(u), pw) => proxy = proxy.basic_auth(u, pw.as_deref().unwrap_or("")),
+                (None, Some(_)) => anyhow::bail!("proxy password requires a username"),
+                (None, None) => {}
+            }
+            if let Some(np) = &p.no_proxy {
+                proxy = proxy.no_proxy(NoProxy::from_string(&np.join(",")));
+            }
+            client_builder = client_builder.proxy(proxy);
+        }
+        Ok(client_builder)
+    }
+}
+
+fn seconds(value: f64, name: &str) -> anyhow::Result<Duration> {
+    Duration::try_from_secs_f64(value)
+        .map_err(|_| anyhow::anyhow!("{name}={value} is not a valid number of seconds"))
+}
+
+/// One redirect that was followed on the way to the final response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub status: StatusCode,
+    pub from: Url,
+    pub to: Url,
+}
+
+/// The redirects followed for a response, stored in its extensions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectChain(pub Vec<Redirect>);
+
+/// The client used for a single `Request::send`. It records the redirects
+/// it follows and attaches them to each response as a `RedirectChain`.
+pub struct HttpClient {
+    client: Client,
+    redirects: Arc<Mutex<Vec<Redirect>>>,
+}
+
+impl HttpClient {
+    pub fn build(
+        client_builder: ClientBuilder,
+        max_redirects: Option<usize>,
+    ) -> anyhow::Result<Self> {
+        let max = max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+        let redirects = Arc::new(Mutex::new(Vec::new()));
+        let log = redirects.clone();
+        let policy = Policy::custom(move |attempt| {
+            if max == 0 {
+                return attempt.stop();
+            }
+            // previous() starts with the original url, so it holds one entry
+            // per redirect followed so far
+            if attempt.previous().len() > max {
+                return attempt.error(format!("stopped after max_redirects={max}"));
+            }
+            let from = attempt
+                .previous()
+                .last()
+                .cloned()
+                .expect("a redirect always has a previous url");
+            log.lock().unwrap().push(Redirect {
+                status: attempt.status(),
+                from,
+                to: attempt.url().clone(),
+            });
+            attempt.follow()
+        });
+        Ok(HttpClient {
+            client: client_builder.redirect(policy).build()?,
+            redirects,
+        })
+    }
+
+    pub fn execute(&self, req: Request) -> anyhow::Result<Response> {
+        self.redirects.lock().unwrap().clear();
+        let mut resp = self.client.execute(req)?;
+        let chain = std::mem::take(&mut *self.redirects.lock().unwrap());
+        resp.extensions_mut().insert(RedirectChain(chain));
+        Ok(resp)
+    }
+}
+
+impl Deref for HttpClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{header, serve, serve_once, OK};
+
+    fn client(conf: &ClientConfig) -> HttpClient {
+        HttpClient::build(
+            conf.configure(Client::builder()).unwrap(),
+            conf.max_redirects,
+        )
+        .unwrap()
+    }
+
+    fn get(client: &HttpClient, url: &str) -> anyhow::Result<Response> {
+        client.execute(client.get(url).build()?)
+    }
+
+    /// Serves /a -> /b -> /c, answering /c with a 200.
+    fn redirect_server(count: usize) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        serve(count, |raw| {
+            let (status, location) = if raw.starts_with("GET /a ") {
+                ("302 Found", "/b")
+            } else if raw.starts_with("GET /b ") {
+                ("301 Moved Permanently", "/c")
+            } else {
+                return "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                    .to_string();
+            };
+            format!(
+                "HTTP/1.1 {status}\r\nconnection: close\r\nlocation: {location}\r\n\
+                 content-length: 0\r\n\r\n"
+            )
+        })
+    }
+
+    #[test]
+    fn test_client_deserialize() {
+        let conf: ClientConfig = serde_json::from_str(
+            r#"{"connect_timeout": 1.5, "max_redirects": 0,
+                "proxy": {"url": "socks5://127.0.0.1:1080", "no_proxy": ["localhost"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(conf.connect_timeout, Some(1.5));
+        assert_eq!(conf.timeout, None);
+        assert_eq!(conf.max_redirects, Some(0));
+        assert_eq!(
+            conf.proxy.unwrap().no_proxy,
+            Some(vec!["localhost".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_client_redirect_chain() {
+        let (addr, handle) = redirect_server(3);
+        let resp = get(&client(&ClientConfig::default()), &format!("{addr}/a")).unwrap();
+        assert_eq!(resp.status(), 200);
+        let chain = &resp.extensions().get::<RedirectChain>().unwrap().0;
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].status, 302);
+        assert_eq!(chain[0].from.as_str(), format!("{addr}/a"));
+        assert_eq!(chain[0].to.as_str(), format!("{addr}/b"));
+        assert_eq!(chain[1].status, 301);
+        assert_eq!(chain[1].to.as_str(), format!("{addr}/c"));
+        assert_eq!(handle.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_client_max_redirects() {
+        let (addr, handle) = redirect_server(1);
+        let conf = ClientConfig {
+            max_redirects: Some(0),
+            ..Default::default()
+        };
+        let resp = get(&client(&conf), &format!("{addr}/a")).unwrap();
+        assert_eq!(resp.status(), 302);
+        assert!(resp
+            .extensions()
+            .get::<RedirectChain>()
+            .unwrap()
+            .0
+            .is_empty());
+        handle.join().unwrap();
+
+        let (addr, handle) = redirect_server(2);
+        let conf = ClientConfig {
+            max_redirects: Some(1),
+            ..Default::default()
+        };
+        let err = get(&client(&conf), &format!("{addr}/a")).unwrap_err();
+        assert!(format!("{err:#}").contains("stopped after max_redirects=1"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_client_timeout() {
+        let (addr, handle) = serve(1, |_| {
+            std::thread::sleep(Duration::from_millis(1000));
+            OK.to_string()
+        });
+        let conf = ClientConfig {
+            timeout: Some(0.2),
+            ..Default::default()
+        };
+        let err = get(&client(&conf), &addr).unwrap_err();
+        assert!(err.downcast_ref::<reqwest::Error>().unwrap().is_timeout());
+        handle.join().unwrap();
+
+        let conf = ClientConfig {
+            timeout: Some(-1.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.configure(Client::builder()).unwrap_err().to_string(),
+            "timeout=-1 is not a valid number of seconds"
+        );
+    }
+
+    #[test]
+    fn test_client_proxy() {
+        let (proxy, handle) = serve_once(OK);
+        let conf = ClientConfig {
+            proxy: Some(ProxyConfig {
+                url: proxy,
+                username: Some("user".to_string()),
+                password: Some("pass".to_string()),
+                no_proxy: None,
+            }),
+            ..Default::default()
+        };
+        let resp = get(&client(&conf), "http://example.invalid/path?q=1").unwrap();
+        assert_eq!(resp.status(), 200);
+        let raw = handle.join().unwrap();
+        assert!(raw.starts_with("GET http://example.invalid/path?q=1 HTTP/1.1\r\n"));
+        assert_eq!(
+            header(&raw, "proxy-authorization"),
+            Some("Basic dXNlcjpwYXNz")
+        );
+    }
+
+    #[test]
+    fn test_client_no_proxy() {
+        let (addr, handle) = serve_once(OK);
+        let conf = ClientConfig {
+            proxy: Some(ProxyConfig {
+                // nothing listens here, the request only works if it bypasses
+                // the proxy
+                url: "http://127.0.0.1:1".to_string(),
+                no_proxy: Some(vec!["127.0.0.1".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(get(&client(&conf), &addr).unwrap().status(), 200);
+        assert!(handle.join().unwrap().starts_with("GET / "));
+    }
+}
@@ -0,0 +1,237 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::auth::Auth;
+use crate::client::ClientConfig;
+use crate::diagnostics::explain_parse_error;
+use crate::extends::merge_request;
+use crate::parser::{parse_value, MultiMap, RequestFormat};
+use crate::tls::TlsConfig;
+
+/// Names of the collection config file, in the order they are looked for in
+/// each directory.
+pub const CONFIG_FILES: [&str; 2] = ["rascal.toml", ".rascal.json"];
+
+/// Defaults shared by every request in a collection, merged under the fields
+/// of each request file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionConfig {
+    // relative request urls are appended to this
+    #[serde(skip_serializing)]
+    pub base_url: Option<String>,
+    // sent as the user-agent header unless the request sets one
+    #[serde(skip_serializing)]
+    pub user_agent: Option<String>,
+    // template variables, overridden by the environment file and `-k`
+    #[serde(skip_serializing)]
+    pub variables: Option<Map<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<MultiMap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Auth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientConfig>,
+}
+
+/// The directory a request file is in, `.` for a bare file name.
+pub fn request_dir(file: &str) -> &Path {
+    match Path::new(file).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// The nearest collection config, looking in `dir` and then in each of its
+/// parents.
+pub fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.canonicalize()
+        .ok()?
+        .ancestors()
+        .flat_map(|d| CONFIG_FILES.iter().map(move |name| d.join(name)))
+        .find(|p| p.is_file())
+}
+
+impl CollectionConfig {
+    /// Read the config at `path`. Like request files, it is a template and
+    /// is rendered with `render` first. Relative paths in `tls` are resolved
+    /// against the directory of the config, not the working directory.
+    pub fn load(
+        path: &Path,
+        render: &dyn Fn(&str, &str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<Self> {
+        let name = path.display().to_string();
+        let template = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read from file={name}"))?;
+        let rendered = render(&name, &template)?;
+        let value = parse_value(&rendered, RequestFormat::from_path(&name))
+            .map_err(|e| anyhow!(explain_parse_error(&name, &template, &rendered, &e)))?;
+        let mut config: Self = serde_json::from_value(value)
+            .with_context(|| format!("invalid collection config in file={name}"))?;
+        if let (Some(tls), Some(dir)) = (&mut config.tls, path.parent()) {
+            tls.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// Merge `request` over the collection defaults and resolve a relative
+    /// url against `base_url`.
+    pub fn apply(&self, request: Value) -> anyhow::Result<Value> {
+        let Value::Object(fields) = request else {
+            return Ok(request);
+        };
+        let Value::Object(mut defaults) = serde_json::to_value(self)? else {
+            unreachable!("the config serializes to an object");
+        };
+        if let Some(agent) = &self.user_agent {
+            let headers = defaults
+                .entry("headers")
+                .or_insert_with(|| Value::Object(Map::new()));
+            if let Value::Object(h) = headers {
+                if !h.keys().any(|k| k.eq_ignore_ascii_case("user-agent")) {
+                    h.insert("user-agent".to_string(), Value::String(agent.clone()));
+                }
+            }
+        }
+
+        let mut merged = merge_request(defaults, fields);
+        if let (Some(base), Some(Value::String(url))) = (&self.base_url, merged.get_mut("url")) {
+            *url = join_url(base, url);
+        }
+        Ok(Value::Object(merged))
+    }
+}
+
+/// Append `url` to `base` unless it is absolute already.
+fn join_url(base: &str, url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else if url.is_empty() || url.starts_with(['?', '#']) {
+        format!("{base}{url}")
+    } else {
+        format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            url.trim_start_matches('/')
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn render(_: &str, template: &str) -> anyhow::Result<String> {
+        Ok(template.to_string())
+    }
+
+    #[test]
+    fn test_join_url() {
+        let base = "https://example.com/api/";
+        assert_eq!(join_url(base, "/users"), "https://example.com/api/users");
+        assert_eq!(
+            join_url("https://example.com/api", "users?page=2"),
+            "https://example.com/api/users?page=2"
+        );
+        assert_eq!(join_url(base, "?page=2"), "https://example.com/api/?page=2");
+        assert_eq!(join_url(base, "http://other.com/x"), "http://other.com/x");
+    }
+
+    #[test]
+    fn test_collection_apply() {
+        let config: CollectionConfig = toml::from_str(
+            r#"
+base_url = "https://example.com/api"
+user_agent = "rascal-tests"
+
+[variables]
+version = 2
+
+[headers]
+accept = "*/*"
+x-team = "core"
+
+[auth]
+type = "Bearer"
+token = "t"
+
+[client]
+timeout = 5.0
+"#,
+        )
+        .unwrap();
+        let request = json!({
+            "method": "GET",
+            "url": "users/1",
+            "headers": {"Accept": "application/json"},
+            "client": {"max_redirects": 0}
+        });
+        assert_eq!(
+            config.apply(request).unwrap(),
+            json!({
+                "headers": {
+                    "x-team": "core",
+                    "user-agent": "rascal-tests",
+                    "Accept": "application/json"
+                },
+                "auth": {"type": "Bearer", "token": "t"},
+                "client": {"timeout": 5.0, "max_redirects": 0},
+                "method": "GET",
+                "url": "https://example.com/api/users/1"
+            })
+        );
+
+        // the request's own user agent and auth win
+        let request = json!({
+            "url": "https://other.com",
+            "headers": {"User-Agent": "curl"},
+            "auth": null
+        });
+        assert_eq!(
+            config.apply(request).unwrap(),
+            json!({
+                "headers": {"accept": "*/*", "x-team": "core", "User-Agent": "curl"},
+                "client": {"timeout": 5.0},
+                "url": "https://other.com"
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_and_load_config() {
+        let dir = std::env::temp_dir().join(format!("rascal-collection-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("users/admin")).unwrap();
+        std::fs::write(dir.join("rascal.toml"), "base_url = \"https://a\"\n").unwrap();
+        std::fs::write(
+            dir.join("users/.rascal.json"),
+            r#"{"base_url": "https://b"}"#,
+        )
+        .unwrap();
+        let found = |file: &str| find_config(request_dir(&dir.join(file).display().to_string()));
+
+        let users = found("users/admin/get.json").unwrap();
+        assert_eq!(users.file_name().unwrap(), ".rascal.json");
+        assert_eq!(
+            CollectionConfig::load(&users, &render).unwrap().base_url,
+            Some("https://b".to_string())
+        );
+        assert_eq!(
+            found("get.json").unwrap().file_name().unwrap(),
+            "rascal.toml"
+        );
+
+        std::fs::write(dir.join("rascal.toml"), "base_ur = \"https://a\"\n").unwrap();
+        let err = CollectionConfig::load(&found("get.json").unwrap(), &render).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            format!("{err:#}").contains("unknown field `base_ur`"),
+            "{err:#}"
+        );
+    }
+}
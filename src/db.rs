@@ -12,6 +12,20 @@ CREATE TABLE IF NOT EXISTS cookies (
     http_only BOOLEAN NOT NULL,
     expiry INTEGER NOT NULL
 );";
+static CREATE_OAUTH2_TOKENS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS oauth2_tokens (
+    cache_key TEXT PRIMARY KEY,
+    access_token TEXT NOT NULL,
+    refresh_token TEXT,
+    expires_at INTEGER
+);";
+
+pub struct CachedToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    // Unix timestamp, None if the token endpoint did not say
+    pub expires_at: Option<i64>,
+}
 
 // Create a connection to the sqlite database. Will create the database
 // and associated tables if they do not exist.
@@ -19,12 +33,50 @@ pub fn get_or_create_db() -> anyhow::Result<rusqlite::Connection> {
     let temp_dir = std::env::temp_dir();
     let db_path = temp_dir.join(RASCAL_DB);
     let connection = rusqlite::Connection::open(db_path)?;
+    create_tables(&connection)?;
+    Ok(connection)
+}
 
-    // Create the cookies table if it does not exist
+// Create any tables that do not exist yet on the given connection.
+pub fn create_tables(connection: &rusqlite::Connection) -> anyhow::Result<()> {
     connection.execute(CREATE_COOKIES_TABLE, [])?;
-    Ok(connection)
+    connection.execute(CREATE_OAUTH2_TOKENS_TABLE, [])?;
+    Ok(())
+}
+
+pub fn fetch_oauth2_token(
+    conn: &rusqlite::Connection,
+    cache_key: &str,
+) -> anyhow::Result<Option<CachedToken>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT access_token, refresh_token, expires_at
+        FROM oauth2_tokens
+        WHERE cache_key = ?1",
+    )?;
+    let mut tokens = stmt.query_map([cache_key], |row| {
+        Ok(CachedToken {
+            access_token: row.get(0)?,
+            refresh_token: row.get(1)?,
+            expires_at: row.get(2)?,
+        })
+    })?;
+    Ok(tokens.next().transpose()?)
+}
+
+pub fn save_oauth2_token(
+    conn: &rusqlite::Connection,
+    cache_key: &str,
+    token: &CachedToken,
+) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO oauth2_tokens (cache_key, access_token, refresh_token, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![cache_key, token.access_token, token.refresh_token, token.expires_at],
+    )?;
+    Ok(())
 }
 
+#[allow(dead_code)]
 fn fetch_cookies<'a>(host: String, path: String) -> anyhow::Result<Vec<Cookie<'a>>> {
     let conn = get_or_create_db()?;
     let mut stmt = conn.prepare(
@@ -44,17 +96,17 @@ fn fetch_cookies<'a>(host: String, path: String) -> anyhow::Result<Vec<Cookie<'a
             let expiry: i64 = row.get(6)?;
             let dt = time::OffsetDateTime::from_unix_timestamp(expiry);
 
-            let mut cookieBuilder = Cookie::build((name, value))
+            let mut cookie_builder = Cookie::build((name, value))
                 .domain(domain)
                 .path(path)
                 .secure(secure)
                 .http_only(http_only);
 
             if let Ok(x) = dt {
-                cookieBuilder = cookieBuilder.expires(x);
+                cookie_builder = cookie_builder.expires(x);
             }
 
-            Ok(cookieBuilder.build())
+            Ok(cookie_builder.build())
         })?
         .map(|c| c.unwrap())
         .collect::<Vec<Cookie<'a>>>();
@@ -0,0 +1,268 @@
+use std::error::Error;
+
+use crate::parser::ParseError;
+
+/// Maps lines of a rendered template back to the template they came from.
+/// Lines without template syntax render verbatim, so they are matched up in
+/// order and used as anchors; rendered lines in between are spread over the
+/// template lines between the surrounding anchors.
+pub struct SourceMap<'a> {
+    template: Vec<&'a str>,
+    // template line index for every rendered line index
+    lines: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(template: &'a str, rendered: &str) -> Self {
+        let template: Vec<&str> = template.lines().collect();
+        let rendered: Vec<&str> = rendered.lines().collect();
+
+        let mut anchors = Vec::new();
+        let mut next = 0;
+        for (t, line) in template.iter().enumerate() {
+            // blank lines are everywhere in rendered output, so they make
+            // poor anchors
+            if is_dynamic(line) || line.trim().is_empty() {
+                continue;
+            }
+            if let Some(r) = rendered[next.min(rendered.len())..]
+                .iter()
+                .position(|l| l == line)
+            {
+                anchors.push((t, next + r));
+                next += r + 1;
+            }
+        }
+
+        let mut lines = Vec::with_capacity(rendered.len());
+        let (mut prev_t, mut prev_r) = (None::<usize>, None::<usize>);
+        let end = (template.len(), rendered.len());
+        for &(t, r) in anchors.iter().chain(std::iter::once(&end)) {
+            let first_t = prev_t.map_or(0, |p| p + 1);
+            let first_r = prev_r.map_or(0, |p| p + 1);
+            let last_t = t.saturating_sub(1).max(first_t);
+            for offset in 0..r.saturating_sub(first_r) {
+                lines.push((first_t + offset).min(last_t));
+            }
+            if r < rendered.len() {
+                lines.push(t);
+            }
+            (prev_t, prev_r) = (Some(t), Some(r));
+        }
+        SourceMap { template, lines }
+    }
+
+    /// The 1-based template line and, where it can be known, column for a
+    /// 1-based position in the rendered output.
+    pub fn locate(&self, line: usize, column: Option<usize>) -> (usize, Option<usize>) {
+        let Some(&t) = self.lines.get(line.saturating_sub(1)) else {
+            return (line, column);
+        };
+        let source = self.template.get(t).copied().unwrap_or("");
+        // Columns carry over as long as they point before any template syntax
+        let static_prefix = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|tag| source.find(tag))
+            .min()
+            .unwrap_or(source.len());
+        let column = match column {
+            Some(c) if c <= static_prefix + 1 => Some(c),
+            Some(_) => Some(static_prefix + 1),
+            None => None,
+        };
+        (t + 1, column)
+    }
+}
+
+fn is_dynamic(line: &str) -> bool {
+    line.contains("{{") || line.contains("{%") || line.contains("{#")
+}
+
+/// Format `message` with the source line it points at and a caret under the
+/// column, e.g.
+///
+/// ```text
+/// error: invalid json: expected `,` or `}`
+///   --> req.json:3:5
+///    |
+///  3 |     "url": "https://example.com"
+///    |     ^
+/// ```
+pub fn snippet(
+    file: &str,
+    source: &str,
+    line: usize,
+    column: Option<usize>,
+    message: &str,
+) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let number = line.to_string();
+    let pad = " ".repeat(number.len());
+    let location = match column {
+        Some(c) => format!("{file}:{line}:{c}"),
+        None => format!("{file}:{line}"),
+    };
+    let mut out = format!("{message}\n{pad}--> {location}\n{pad} |\n{number} | {text}\n");
+    if let Some(c) = column {
+        // keep tabs so the caret lines up with the source line
+        let indent: String = text
+            .chars()
+            .take(c.saturating_sub(1))
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!("{pad} | {indent}^\n"));
+    }
+    out
+}
+
+/// The name of the variable a tera render error complains about, if it was
+/// undefined.
+pub fn undefined_variable(err: &tera::Error) -> Option<String> {
+    let mut source: Option<&dyn Error> = Some(err);
+    while let Some(e) = source {
+        let message = e.to_string();
+        if let Some(rest) = message.strip_prefix("Variable `") {
+            if let Some((name, tail)) = rest.split_once('`') {
+                if tail.starts_with(" not found in context") {
+                    return Some(name.to_string());
+                }
+            }
+        }
+        source = e.source();
+    }
+    None
+}
+
+/// The 1-based line and column of the first use of variable `name` inside a
+/// `{{ }}` or `{% %}` tag.
+pub fn find_variable(template: &str, name: &str) -> Option<(usize, usize)> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    for (number, line) in template.lines().enumerate() {
+        let mut start = 0;
+        while let Some(i) = line[start..].find(name) {
+            let at = start + i;
+            let end = at + name.len();
+            let bounded = !line[..at].chars().next_back().is_some_and(is_name)
+                && !line[end..].chars().next().is_some_and(is_name);
+            // inside a tag opened earlier on the line and not closed yet
+            let before = &line[..at];
+            let open = before.rfind("{{").max(before.rfind("{%"));
+            let close = before.rfind("}}").max(before.rfind("%}"));
+            let in_tag = open.is_some() && open > close;
+            if bounded && in_tag {
+                return Some((number + 1, at + 1));
+            }
+            start = end;
+        }
+    }
+    None
+}
+
+/// Explain an undefined template variable, pointing at where it is used.
+pub fn explain_render_error(file: &str, template: &str, err: &tera::Error) -> Option<String> {
+    let name = undefined_variable(err)?;
+    let hint = if let Some(arg) = name.strip_prefix("arg_") {
+        format!(", pass it with `-k {arg}=...`")
+    } else if let Some(var) = name.strip_prefix("vars.") {
+        format!(", pass it with `-k {var}=...` or set it in an environment file or the collection config")
+    } else if let Some(env) = name.strip_prefix("env_") {
+        format!(", set the {env} environment variable")
+    } else {
+        String::new()
+    };
+    let message = format!("undefined template variable `{name}`{hint}");
+    Some(match find_variable(template, &name) {
+        Some((line, column)) => snippet(file, template, line, Some(column), &message),
+        None => format!("{message}\n  --> {file}\n"),
+    })
+}
+
+/// Point a parse error in `rendered` back at the line of `template` it came
+/// from.
+pub fn explain_parse_error(file: &str, template: &str, rendered: &str, err: &ParseError) -> String {
+    match err.line {
+        Some(line) => {
+            let (line, column) = SourceMap::new(template, rendered).locate(line, err.column);
+            snippet(file, template, line, column, &err.message)
+        }
+        None => format!("{}\n  --> {file}\n", err.message),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_source_map_static_lines() {
+        let template = "{\n  \"a\": 1,\n  \"b\": 2\n}";
+        let map = SourceMap::new(template, template);
+        assert_eq!(map.locate(3, Some(4)), (3, Some(4)));
+        assert_eq!(map.locate(4, None), (4, None));
+    }
+
+    #[test]
+    fn test_source_map_expanding_template() {
+        let template = "{\n  \"method\": \"GET\",\n{% for h in hs %}\n  \"{{ h }}\": 1,\n{% endfor %}\n  \"url\": \"{{ arg_url }}\" x\n}";
+        let rendered = "{\n  \"method\": \"GET\",\n\n  \"a\": 1,\n\n  \"b\": 1,\n\n  \"url\": \"http://x\" x\n}";
+        let map = SourceMap::new(template, rendered);
+        assert_eq!(map.locate(2, Some(3)), (2, Some(3)));
+        // lines rendered by the loop stay within the loop
+        for line in 3..=7 {
+            let (t, _) = map.locate(line, None);
+            assert!((3..=6).contains(&t), "rendered line {line} mapped to {t}");
+        }
+        // the closing brace is static and lines up again
+        assert_eq!(map.locate(9, Some(1)), (7, Some(1)));
+        // columns past template syntax point at the start of the tag
+        assert_eq!(map.locate(8, Some(22)).1, Some(11));
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(
+            snippet(
+                "req.json",
+                "{\n  \"url\": x\n}",
+                2,
+                Some(10),
+                "error: bad value"
+            ),
+            "error: bad value\n --> req.json:2:10\n  |\n2 |   \"url\": x\n  |          ^\n"
+        );
+        assert_eq!(
+            snippet("req.json", "{\n}", 2, None, "error"),
+            "error\n --> req.json:2\n  |\n2 | }\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_render_error() {
+        let template = "{\n  \"url\": \"{{ env_HOST }}/{{ arg_id }}\"\n}";
+        let mut tera = tera::Tera::default();
+        tera.add_raw_template("t", template).unwrap();
+        let mut context = tera::Context::new();
+        context.insert("env_HOST", "example.com");
+        let err = tera.render("t", &context).unwrap_err();
+        assert_eq!(undefined_variable(&err).as_deref(), Some("arg_id"));
+        assert_eq!(
+            explain_render_error("req.json", template, &err).unwrap(),
+            "undefined template variable `arg_id`, pass it with `-k id=...`\n \
+             --> req.json:2:29\n  |\n2 |   \"url\": \"{{ env_HOST }}/{{ arg_id }}\"\n  \
+             |                             ^\n"
+        );
+    }
+
+    #[test]
+    fn test_find_variable() {
+        assert_eq!(
+            find_variable("arg_id {{ arg_idx }} {{ arg_id }}", "arg_id"),
+            Some((1, 25))
+        );
+        assert_eq!(
+            find_variable("{% if arg_id %}x{% endif %}", "arg_id"),
+            Some((1, 7))
+        );
+        assert_eq!(find_variable("arg_id", "arg_id"), None);
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+
+use crate::parser::{parse_value, RequestFormat};
+
+/// Name of the directory holding the environment files of a collection.
+pub const ENVIRONMENTS_DIR: &str = "environments";
+
+const EXTENSIONS: [&str; 4] = ["json", "yaml", "yml", "toml"];
+
+/// The environments directory for requests in `dir`: next to the collection
+/// config when there is one, otherwise in `dir` itself.
+pub fn environments_dir(dir: &Path, config: Option<&Path>) -> PathBuf {
+    config
+        .and_then(|c| c.parent())
+        .unwrap_or(dir)
+        .join(ENVIRONMENTS_DIR)
+}
+
+/// Names of the environments in `dir`, sorted.
+pub fn list_environments(dir: &Path) -> anyhow::Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory={}", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_env = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e));
+        if let (true, Some(stem)) = (is_env, path.file_stem().and_then(|s| s.to_str())) {
+            names.push(stem.to_string());
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Read the variables of environment `name` from `dir`. Environment files
+/// hold a single object of variables and are not templates.
+pub fn load_environment(dir: &Path, name: &str) -> anyhow::Result<Map<String, Value>> {
+    let Some(path) = EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|p| p.is_file())
+    else {
+        let available = list_environments(dir)?;
+        return Err(anyhow!(
+            "unknown environment={name} in {}, available: {}",
+            dir.display(),
+            if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            }
+        ));
+    };
+
+    let file = path.display().to_string();
+    let src = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read from file={file}"))?;
+    match parse_value(&src, RequestFormat::from_path(&file)) {
+        Ok(Value::Object(vars)) => Ok(vars),
+        Ok(_) => Err(anyhow!(
+            "environment file={file} must hold an object of variables"
+        )),
+        Err(e) => Err(anyhow!("{e}\n  --> {file}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_environments() {
+        let dir = std::env::temp_dir().join(format!("rascal-environments-{}", std::process::id()));
+        let envs = dir.join(ENVIRONMENTS_DIR);
+        std::fs::create_dir_all(&envs).unwrap();
+        std::fs::write(
+            envs.join("staging.json"),
+            r#"{"host": "staging.example.com", "port": 8443}"#,
+        )
+        .unwrap();
+        std::fs::write(envs.join("dev.yaml"), "host: localhost\n").unwrap();
+        std::fs::write(envs.join("notes.txt"), "").unwrap();
+        std::fs::write(envs.join("broken.toml"), "host = [").unwrap();
+
+        let names = list_environments(&envs).unwrap();
+        let staging = load_environment(&envs, "staging").unwrap();
+        let dev = load_environment(&envs, "dev").unwrap();
+        let missing = load_environment(&envs, "prod").unwrap_err();
+        let broken = load_environment(&envs, "broken").unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, vec!["broken", "dev", "staging"]);
+        assert_eq!(
+            Value::Object(staging),
+            json!({"host": "staging.example.com", "port": 8443})
+        );
+        assert_eq!(dev.get("host"), Some(&json!("localhost")));
+        assert!(missing
+            .to_string()
+            .ends_with("available: broken, dev, staging"));
+        assert!(broken.to_string().starts_with("invalid toml: "));
+        assert_eq!(
+            list_environments(&dir.join("nope")).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_environments_dir() {
+        let config = Path::new("/work/api/rascal.toml");
+        assert_eq!(
+            environments_dir(Path::new("/work/api/users"), Some(config)),
+            Path::new("/work/api/environments")
+        );
+        assert_eq!(
+            environments_dir(Path::new("/work/api/users"), None),
+            Path::new("/work/api/users/environments")
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+
+use crate::diagnostics::explain_parse_error;
+use crate::parser::{parse_value, RequestFormat};
+
+/// Key naming the request a request file is merged over.
+pub const EXTENDS: &str = "extends";
+
+/// Fields replaced as a whole rather than merged, since mixing the keys of
+/// two auth modes or body modes never gives what either file meant.
+const REPLACED: [&str; 2] = ["auth", "body"];
+
+/// Merge `value`, parsed from `file`, over the request it extends, and that
+/// one over the request it extends in turn. Parent paths are relative to the
+/// file that names them, and each parent is rendered with `render` and
+/// parsed in the format of its own extension. Returns `value` as is when it
+/// extends nothing.
+pub fn resolve_extends(
+    file: &str,
+    value: Value,
+    render: &dyn Fn(&str, &str) -> anyhow::Result<String>,
+) -> anyhow::Result<Value> {
+    let path = PathBuf::from(file);
+    let mut chain = vec![canonical(&path)?];
+    resolve(&path, value, render, &mut chain)
+}
+
+fn resolve(
+    file: &Path,
+    mut value: Value,
+    render: &dyn Fn(&str, &str) -> anyhow::Result<String>,
+    chain: &mut Vec<PathBuf>,
+) -> anyhow::Result<Value> {
+    let parent = match value.as_object_mut().and_then(|m| m.shift_remove(EXTENDS)) {
+        None | Some(Value::Null) => return Ok(value),
+        Some(Value::String(p)) => p,
+        Some(other) => anyhow::bail!(
+            "{EXTENDS} must be a path, got {other} in file={}",
+            file.display()
+        ),
+    };
+    let parent = file.parent().unwrap_or(Path::new("")).join(parent);
+    let canonical_parent = canonical(&parent)
+        .with_context(|| format!("failed to resolve {EXTENDS} in file={}", file.display()))?;
+    if let Some(start) = chain.iter().position(|p| *p == canonical_parent) {
+        let cycle: Vec<String> = chain[start..]
+            .iter()
+            .chain(std::iter::once(&canonical_parent))
+            .map(|p| p.display().to_string())
+            .collect();
+        anyhow::bail!("{EXTENDS} cycle: {}", cycle.join(" -> "));
+    }
+    chain.push(canonical_parent);
+
+    let name = parent.display().to_string();
+    let template = std::fs::read_to_string(&parent)
+        .with_context(|| format!("failed to read from file={name}"))?;
+    let rendered = render(&name, &template)?;
+    let parent_value = parse_value(&rendered, RequestFormat::from_path(&name))
+        .map_err(|e| anyhow!(explain_parse_error(&name, &template, &rendered, &e)))?;
+    let (Value::Object(parent_fields), Value::Object(fields)) =
+        (resolve(&parent, parent_value, render, chain)?, value)
+    else {
+        anyhow::bail!("file={name} must hold a request to be extended");
+    };
+    Ok(Value::Object(merge_request(parent_fields, fields)))
+}
+
+fn canonical(path: &Path) -> anyhow::Result<PathBuf> {
+    path.canonicalize()
+        .with_context(|| format!("failed to read from file={}", path.display()))
+}
+
+/// Merge the fields of `child` over `parent`. Objects are merged key by key,
+/// except for `auth` and `body`, and a null in `child` drops the key.
+pub fn merge_request(
+    mut parent: Map<String, Value>,
+    child: Map<String, Value>,
+) -> Map<String, Value> {
+    for (key, value) in child {
+        if REPLACED.contains(&key.as_str()) {
+            match value {
+                Value::Null => parent.shift_remove(&key),
+                value => parent.insert(key, value),
+            };
+        } else if key == "headers" {
+            let merged = merge_headers(parent.get_mut(&key).map(Value::take), value);
+            parent.insert(key, merged);
+        } else {
+            merge_key(&mut parent, key, value);
+        }
+    }
+    parent
+}
+
+/// Header names are case insensitive, so `Accept` in the child replaces
+/// `accept` in the parent.
+fn merge_headers(parent: Option<Value>, child: Value) -> Value {
+    match (parent, child) {
+        (Some(Value::Object(mut parent)), Value::Object(child)) => {
+            for (name, value) in child {
+                if let Some(existing) = parent
+                    .keys()
+                    .find(|k| k.eq_ignore_ascii_case(&name))
+                    .cloned()
+                {
+                    parent.shift_remove(&existing);
+                }
+                if !value.is_null() {
+                    parent.insert(name, value);
+                }
+            }
+            Value::Object(parent)
+        }
+        (_, child) => child,
+    }
+}
+
+fn merge(parent: Value, child: Value) -> Value {
+    match (parent, child) {
+        (Value::Object(mut parent), Value::Object(child)) => {
+            for (key, value) in child {
+                merge_key(&mut parent, key, value);
+            }
+            Value::Object(parent)
+        }
+        (_, child) => child,
+    }
+}
+
+fn merge_key(parent: &mut Map<String, Value>, key: String, value: Value) {
+    if value.is_null() {
+        parent.shift_remove(&key);
+        return;
+    }
+    let merged = match parent.get_mut(&key) {
+        Some(existing) => merge(existing.take(), value),
+        None => value,
+    };
+    parent.insert(key, merged);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn render(_: &str, template: &str) -> anyhow::Result<String> {
+        Ok(template.replace("{{ arg_host }}", "example.com"))
+    }
+
+    /// Write `files` into a fresh directory and resolve the first one.
+    fn resolve_files(files: &[(&str, &str)]) -> anyhow::Result<Value> {
+        let dir = std::env::temp_dir().join(format!(
+            "rascal-extends-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        for (name, content) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let file = dir.join(files[0].0).display().to_string();
+        let value = serde_json::from_str(files[0].1).unwrap();
+        let result = resolve_extends(&file, value, &render);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn merge_values(parent: Value, child: Value) -> Value {
+        match (parent, child) {
+            (Value::Object(p), Value::Object(c)) => Value::Object(merge_request(p, c)),
+            _ => panic!("requests are objects"),
+        }
+    }
+
+    #[test]
+    fn test_merge_request() {
+        let parent = json!({
+            "method": "GET",
+            "url": {"host": "example.com", "path": "/a"},
+            "params": {"page": "1", "limit": "10"},
+            "headers": {"accept": "*/*", "x-trace": "1"},
+            "auth": {"type": "Basic", "username": "u", "password": "p"},
+            "body": {"raw": "x"},
+            "client": {"timeout": 5.0}
+        });
+        let child = json!({
+            "method": "POST",
+            "url": {"path": "/b"},
+            "params": {"page": "2"},
+            "headers": {"Accept": "application/json", "x-trace": null},
+            "auth": {"type": "Bearer", "token": "t"},
+            "body": {"json": {}},
+            "client": null
+        });
+        assert_eq!(
+            merge_values(parent, child),
+            json!({
+                "method": "POST",
+                "url": {"host": "example.com", "path": "/b"},
+                "params": {"page": "2", "limit": "10"},
+                "headers": {"Accept": "application/json"},
+                "auth": {"type": "Bearer", "token": "t"},
+                "body": {"json": {}}
+            })
+        );
+
+        // a url string replaces a url object and the other way round
+        assert_eq!(
+            merge_values(
+                json!({"url": {"host": "example.com"}}),
+                json!({"url": "https://example.org"})
+            ),
+            json!({"url": "https://example.org"})
+        );
+    }
+
+    #[test]
+    fn test_resolve_extends_levels() {
+        let value = resolve_files(&[
+            (
+                "users/get.json",
+                r#"{"extends": "../api.yaml", "method": "GET", "url": "https://example.com/users"}"#,
+            ),
+            (
+                "api.yaml",
+                "extends: base.json\nheaders:\n  x-api: v2\n  x-host: '{{ arg_host }}'\nretry:\n  max_attempts: 5\n",
+            ),
+            (
+                "base.json",
+                r#"{"headers": {"accept": "*/*", "x-api": "v1"}, "auth": {"type": "Bearer", "token": "t"}}"#,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "headers": {"accept": "*/*", "x-api": "v2", "x-host": "example.com"},
+                "auth": {"type": "Bearer", "token": "t"},
+                "retry": {"max_attempts": 5},
+                "method": "GET",
+                "url": "https://example.com/users"
+            })
+        );
+    }
+
+    #[test]
+    fn test_resolve_extends_errors() {
+        let err = resolve_files(&[
+            ("a.json", r#"{"extends": "b.json", "method": "GET"}"#),
+            ("b.json", r#"{"extends": "c.json"}"#),
+            ("c.json", r#"{"extends": "b.json"}"#),
+        ])
+        .unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with("extends cycle: "), "{message}");
+        assert!(message.contains("c.json -> "), "{message}");
+        assert_eq!(message.matches("b.json").count(), 2, "{message}");
+        assert!(!message.contains("a.json"), "{message}");
+
+        let err = resolve_files(&[("a.json", r#"{"extends": "a.json"}"#)]).unwrap_err();
+        assert!(err.to_string().starts_with("extends cycle: "));
+
+        let err = resolve_files(&[("a.json", r#"{"extends": "missing.json"}"#)]).unwrap_err();
+        assert!(format!("{err:#}").starts_with("failed to resolve extends in file="));
+
+        let err = resolve_files(&[("a.json", r#"{"extends": 1}"#)]).unwrap_err();
+        assert!(err.to_string().starts_with("extends must be a path, got 1"));
+
+        let err = resolve_files(&[
+            ("a.json", r#"{"extends": "b.json"}"#),
+            ("b.json", "{\n  x\n}"),
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("b.json:2:3\n"), "{err}");
+    }
+}
@@ -0,0 +1,315 @@
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use log::warn;
+use reqwest::blocking::Request;
+use reqwest::header::{HeaderName, HeaderValue};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::parser::StringOrVec;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    #[default]
+    Unix,
+    UnixMillis,
+    Rfc3339,
+}
+
+/// One line of the canonical string. Everything is taken from the final
+/// request, so the path and query are still percent-encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CanonicalPart {
+    Method,
+    Host,
+    Path,
+    Query,
+    Timestamp,
+    /// Digest of the body with the configured algorithm, in the signature
+    /// encoding.
+    BodyDigest,
+    /// Value of a request header, empty if it is not set.
+    Header(String),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HmacConfig {
+    secret: String,
+    #[serde(default)]
+    algorithm: HmacAlgorithm,
+    #[serde(default = "default_parts")]
+    parts: Vec<CanonicalPart>,
+    #[serde(default = "default_separator")]
+    separator: String,
+    // one header name, or a list of them that all get the signature
+    signature_header: StringOrVec,
+    // prepended to the signature, e.g. "HMAC-SHA256 "
+    signature_prefix: Option<String>,
+    #[serde(default)]
+    signature_encoding: SignatureEncoding,
+    timestamp_header: Option<StringOrVec>,
+    #[serde(default)]
+    timestamp_format: TimestampFormat,
+}
+
+fn default_parts() -> Vec<CanonicalPart> {
+    vec![
+        CanonicalPart::Method,
+        CanonicalPart::Path,
+        CanonicalPart::Timestamp,
+        CanonicalPart::BodyDigest,
+    ]
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+impl HmacConfig {
+    /// The credentials in the config, to be kept out of any output.
+    pub fn secrets(&self) -> Vec<String> {
+        vec![self.secret.clone()]
+    }
+
+    /// Sign the fully built request in place, adding the timestamp and
+    /// signature headers. Every header listed gets the same value.
+    pub fn sign(&self, req: &mut Request) -> anyhow::Result<()> {
+        self.sign_at(req, OffsetDateTime::now_utc())
+    }
+
+    fn sign_at(&self, req: &mut Request, now: OffsetDateTime) -> anyhow::Result<()> {
+        let timestamp = match self.timestamp_format {
+            TimestampFormat::Unix => now.unix_timestamp().to_string(),
+            TimestampFormat::UnixMillis => (now.unix_timestamp_nanos() / 1_000_000).to_string(),
+            TimestampFormat::Rfc3339 => now.format(&Rfc3339)?,
+        };
+        for h in self.timestamp_header.iter().flat_map(StringOrVec::iter) {
+            req.headers_mut()
+                .insert(header_name(h)?, HeaderValue::from_str(&timestamp)?);
+        }
+
+        let canonical = self.canonical_string(req, &timestamp)?;
+        let signature = self.encode(&self.mac(canonical.as_bytes()));
+        let value = format!(
+            "{}{signature}",
+            self.signature_prefix.as_deref().unwrap_or("")
+        );
+        let mut signed = false;
+        for h in self.signature_header.iter() {
+            if req
+                .headers_mut()
+                .insert(header_name(h)?, HeaderValue::from_str(&value)?)
+                .is_some()
+            {
+                warn!("{h} header already exists, overwriting with auth block");
+            }
+            signed = true;
+        }
+        if !signed {
+            anyhow::bail!("Hmac signature_header needs at least one header name");
+        }
+        Ok(())
+    }
+
+    fn canonical_string(&self, req: &Request, timestamp: &str) -> anyhow::Result<String> {
+        let url = req.url();
+        let parts = self
+            .parts
+            .iter()
+            .map(|part| {
+                Ok(match part {
+                    CanonicalPart::Method => req.method().to_string(),
+                    CanonicalPart::Host => match (url.host_str(), url.port()) {
+                        (Some(h), Some(p)) => format!("{h}:{p}"),
+                        (Some(h), None) => h.to_string(),
+                        (None, _) => anyhow::bail!("cannot sign url={url} without a host"),
+                    },
+                    CanonicalPart::Path => url.path().to_string(),
+                    CanonicalPart::Query => url.query().unwrap_or("").to_string(),
+                    CanonicalPart::Timestamp => timestamp.to_string(),
+                    CanonicalPart::BodyDigest => {
+                        let body = match req.body() {
+                            None => &[][..],
+                            Some(b) => b.as_bytes().ok_or_else(|| {
+                                anyhow::anyhow!("cannot compute the body digest of a streamed body")
+                            })?,
+                        };
+                        self.encode(&self.digest(body))
+                    }
+                    CanonicalPart::Header(name) => req
+                        .headers()
+                        .get(name.as_str())
+                        .map(|v| v.to_str())
+                        .transpose()?
+                        .unwrap_or("")
+                        .to_string(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        Ok(parts.join(&self.separator))
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let key = self.secret.as_bytes();
+        match self.algorithm {
+            HmacAlgorithm::Sha256 => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac =
+                    Hmac::<Sha512>::new_from_slice(key).expect("hmac accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            HmacAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HmacAlgorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn encode(&self, bytes: &[u8]) -> String {
+        match self.signature_encoding {
+            SignatureEncoding::Hex => hex::encode(bytes),
+            SignatureEncoding::Base64 => BASE64_STANDARD.encode(bytes),
+        }
+    }
+}
+
+fn header_name(name: &str) -> anyhow::Result<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| anyhow::anyhow!("invalid header name={name:?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::Method;
+
+    fn config(json: &str) -> HmacConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn now() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(1700000000).unwrap()
+    }
+
+    fn post(body: &str) -> Request {
+        let mut req = Request::new(
+            Method::POST,
+            "https://api.example.com/v1/items?b=2&a=1".parse().unwrap(),
+        );
+        *req.body_mut() = Some(body.to_string().into());
+        req
+    }
+
+    #[test]
+    fn test_hmac_defaults() {
+        let conf = config(
+            r#"{"secret": "s3cr3t", "signature_header": "x-signature",
+                "timestamp_header": "x-timestamp"}"#,
+        );
+        assert_eq!(
+            conf.canonical_string(&post("{}"), "1700000000").unwrap(),
+            "POST\n/v1/items\n1700000000\n\
+             44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+
+        let mut req = post("{}");
+        conf.sign_at(&mut req, now()).unwrap();
+        assert_eq!(req.headers().get("x-timestamp").unwrap(), "1700000000");
+        assert_eq!(
+            req.headers().get("x-signature").unwrap(),
+            "6b7fb6f5ffea24458e546cab41c9b03afb4ad3d8b49858056bc624d3cd3eaeaf"
+        );
+    }
+
+    #[test]
+    fn test_hmac_configured_parts() {
+        let conf = config(
+            r#"{"secret": "s3cr3t", "algorithm": "sha512", "separator": "|",
+                "parts": ["method", "host", "path", "query", "timestamp", {"header": "x-request-id"}],
+                "signature_header": "authorization", "signature_prefix": "HMAC ",
+                "signature_encoding": "base64", "timestamp_header": "date",
+                "timestamp_format": "rfc3339"}"#,
+        );
+        let mut req = post("");
+        req.headers_mut()
+            .insert("x-request-id", HeaderValue::from_static("42"));
+        assert_eq!(
+            conf.canonical_string(&req, "2023-11-14T22:13:20Z").unwrap(),
+            "POST|api.example.com|/v1/items|b=2&a=1|2023-11-14T22:13:20Z|42"
+        );
+        conf.sign_at(&mut req, now()).unwrap();
+        assert_eq!(req.headers().get("date").unwrap(), "2023-11-14T22:13:20Z");
+        assert_eq!(
+            req.headers().get("authorization").unwrap(),
+            "HMAC BPBDD8WnD8MlCzVAxhfYWZQOCRMz46RfNfFfhJ7zdpqSYCWh7LbHq+L0hzZDfqk0itk7S1OtqfZ+WJOwvSaAjg=="
+        );
+    }
+
+    #[test]
+    fn test_hmac_several_headers() {
+        let conf = config(
+            r#"{"secret": "s3cr3t", "signature_header": ["x-signature", "x-legacy-signature"],
+                "timestamp_header": ["x-timestamp", "x-legacy-timestamp"]}"#,
+        );
+        let mut req = post("{}");
+        conf.sign_at(&mut req, now()).unwrap();
+        for h in ["x-timestamp", "x-legacy-timestamp"] {
+            assert_eq!(req.headers().get(h).unwrap(), "1700000000");
+        }
+        for h in ["x-signature", "x-legacy-signature"] {
+            assert_eq!(
+                req.headers().get(h).unwrap(),
+                "6b7fb6f5ffea24458e546cab41c9b03afb4ad3d8b49858056bc624d3cd3eaeaf"
+            );
+        }
+
+        let conf = config(r#"{"secret": "s3cr3t", "signature_header": []}"#);
+        assert_eq!(
+            conf.sign_at(&mut post(""), now()).unwrap_err().to_string(),
+            "Hmac signature_header needs at least one header name"
+        );
+    }
+
+    #[test]
+    fn test_hmac_unix_millis() {
+        let conf = config(
+            r#"{"secret": "k", "signature_header": "x-sig", "timestamp_header": "x-ts",
+                "timestamp_format": "unix_millis", "parts": ["timestamp"]}"#,
+        );
+        let mut req = post("");
+        conf.sign_at(&mut req, now()).unwrap();
+        assert_eq!(req.headers().get("x-ts").unwrap(), "1700000000000");
+    }
+}
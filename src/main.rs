@@ -1,10 +1,34 @@
+use std::path::Path;
+
+use crate::collection::{find_config, request_dir};
 use crate::db::get_or_create_db;
-use crate::executer::{execute, format_output};
+use crate::environment::{environments_dir, list_environments};
+use crate::executer::{execute, format_output, load_request};
+use crate::parser::RequestFormat;
+use crate::secrets::{redact, redact_error};
+use crate::validate::{request_files, request_schema, validate_file};
 use clap::{Parser, Subcommand};
+use serde_json::Value;
 
+mod auth;
+mod client;
+mod collection;
 mod db;
+mod diagnostics;
+mod environment;
 mod executer;
+mod extends;
+mod hmac_auth;
+mod oauth2;
 mod parser;
+mod retry;
+mod secrets;
+mod sigv4;
+mod templates;
+#[cfg(test)]
+mod test_util;
+mod tls;
+mod validate;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -21,31 +45,78 @@ struct Args {
 enum Commands {
     Exec {
         input_file: String,
+        /// Request file format, picked from the file extension when unset
+        #[arg(long, value_enum)]
+        format: Option<RequestFormat>,
+        /// Environment whose variables are used, from the collection's
+        /// environments directory
+        #[arg(short, long)]
+        env: Option<String>,
         #[arg(short, long)]
         output_file: Option<String>,
-        #[arg(short, long, value_parser = parse_key_val::<String, String>)]
-        kwargs: Vec<(String, String)>,
+        /// Template arguments, `KEY=value` or `KEY:=json` for a typed value
+        #[arg(short, long, value_parser = parse_kwarg)]
+        kwargs: Vec<(String, Value)>,
         #[arg(short, long, action)]
         full_response: bool,
         #[arg(short, long, action)]
         pretty_print: bool,
     },
+    /// Check request files, or directories of them, without sending anything.
+    /// Templates are rendered with placeholders for unset variables.
+    Validate {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Request file format, picked from the file extension when unset
+        #[arg(long, value_enum)]
+        format: Option<RequestFormat>,
+        /// Environment whose variables are used, from the collection's
+        /// environments directory
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Template arguments, `KEY=value` or `KEY:=json` for a typed value
+        #[arg(short, long, value_parser = parse_kwarg)]
+        kwargs: Vec<(String, Value)>,
+    },
+    /// Print a request merged over the requests it extends, with templates
+    /// rendered, without sending it
+    Resolve {
+        input_file: String,
+        /// Request file format, picked from the file extension when unset
+        #[arg(long, value_enum)]
+        format: Option<RequestFormat>,
+        /// Environment whose variables are used, from the collection's
+        /// environments directory
+        #[arg(short, long)]
+        env: Option<String>,
+        /// Template arguments, `KEY=value` or `KEY:=json` for a typed value
+        #[arg(short, long, value_parser = parse_kwarg)]
+        kwargs: Vec<(String, Value)>,
+    },
+    /// List the environments available to requests in a directory
+    Environments {
+        #[arg(default_value = ".")]
+        path: String,
+    },
+    /// Print the JSON Schema for request files
+    Schema,
+    /// Describe the variables, functions and filters usable in request files
+    #[command(long_about = templates::HELP)]
+    Templates,
 }
 
-/// Parse a single key-value pair
-fn parse_key_val<T, U>(
-    s: &str,
-) -> Result<(T, U), Box<dyn std::error::Error + Send + Sync + 'static>>
-where
-    T: std::str::FromStr,
-    T::Err: std::error::Error + Send + Sync + 'static,
-    U: std::str::FromStr,
-    U::Err: std::error::Error + Send + Sync + 'static,
-{
-    let pos = s
-        .find('=')
+/// Parse a `-k` argument, `KEY=value` for a string or `KEY:=json` for a
+/// number, boolean, list or object
+fn parse_kwarg(s: &str) -> Result<(String, Value), String> {
+    let (key, value) = s
+        .split_once('=')
         .ok_or_else(|| format!("invalid KEY=value: no `=` found in `{s}`"))?;
-    Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
+    match key.strip_suffix(':') {
+        Some(key) => serde_json::from_str(value)
+            .map(|v| (key.to_string(), v))
+            .map_err(|e| format!("invalid JSON for `{key}`, error={e}")),
+        None => Ok((key.to_string(), Value::String(value.to_string()))),
+    }
 }
 
 fn main() {
@@ -60,19 +131,94 @@ fn main() {
     match args.cmd {
         Commands::Exec {
             input_file,
+            format,
+            env,
             output_file,
             kwargs,
             full_response,
             pretty_print,
         } => {
-            let output = execute(&input_file, kwargs.into_iter().collect(), db_conn)
-                .and_then(|r| format_output(r, full_response, pretty_print, output_file));
+            let output = execute(
+                &input_file,
+                format,
+                env.as_deref(),
+                kwargs.into_iter().collect(),
+                db_conn,
+            )
+            .and_then(|r| format_output(r, full_response, pretty_print, output_file));
             match output {
                 Ok(s) => {
                     println!("{}", s);
                 }
-                Err(e) => eprintln!("🤦 {:?}", e),
+                Err(e) => eprintln!("🤦 {:?}", redact_error(e)),
+            }
+        }
+        Commands::Validate {
+            paths,
+            format,
+            env,
+            kwargs,
+        } => {
+            let kwarg_map = kwargs.into_iter().collect();
+            let files = match request_files(&paths) {
+                Ok(f) => f,
+                Err(e) => {
+                    eprintln!("🤦 {:?}", redact_error(e));
+                    std::process::exit(1);
+                }
+            };
+            let mut failed = false;
+            for file in files {
+                let problems = validate_file(&file, format, env.as_deref(), &kwarg_map);
+                if problems.is_empty() {
+                    println!("ok: {}", file.display());
+                }
+                for p in problems {
+                    failed = true;
+                    eprintln!("{}", redact(&p.to_string()));
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Commands::Resolve {
+            input_file,
+            format,
+            env,
+            kwargs,
+        } => {
+            let kwarg_map = kwargs.into_iter().collect();
+            let resolved = load_request(&input_file, format, env.as_deref(), &kwarg_map)
+                .and_then(|(_, value)| Ok(serde_json::to_string_pretty(&value)?));
+            match resolved {
+                Ok(s) => println!("{}", redact(&s)),
+                Err(e) => {
+                    eprintln!("🤦 {:?}", redact_error(e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Environments { path } => {
+            let dir = Path::new(&path);
+            let dir = if dir.is_dir() {
+                dir
+            } else {
+                request_dir(&path)
+            };
+            let envs_dir = environments_dir(dir, find_config(dir).as_deref());
+            match list_environments(&envs_dir) {
+                Ok(names) if names.is_empty() => {
+                    eprintln!("no environments found in {}", envs_dir.display())
+                }
+                Ok(names) => names.iter().for_each(|n| println!("{n}")),
+                Err(e) => {
+                    eprintln!("🤦 {:?}", redact_error(e));
+                    std::process::exit(1);
+                }
             }
         }
+        Commands::Schema => print!("{}", request_schema()),
+        Commands::Templates => print!("{}", templates::HELP),
     }
 }
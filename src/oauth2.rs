@@ -0,0 +1,335 @@
+use anyhow::Context;
+use log::{info, warn};
+use reqwest::blocking::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{fetch_oauth2_token, save_oauth2_token, CachedToken};
+use crate::secrets::redact;
+
+/// Tokens are treated as expired this many seconds early, so a token does not
+/// run out between being read from the cache and reaching the server.
+const EXPIRY_SKEW_SECS: i64 = 30;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    ClientCredentials,
+    Password,
+    RefreshToken,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OAuth2Config {
+    token_url: String,
+    grant_type: GrantType,
+    client_id: String,
+    client_secret: Option<String>,
+    scope: Option<String>,
+    // required for the password grant
+    username: Option<String>,
+    password: Option<String>,
+    // required for the refresh_token grant
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    refresh_token: Option<String>,
+}
+
+impl OAuth2Config {
+    /// The credentials in the config, to be kept out of any output.
+    pub fn secrets(&self) -> Vec<String> {
+        [&self.client_secret, &self.password, &self.refresh_token]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Get an access token. A still-valid cached token is reused unless
+    /// `force_refresh` is set, e.g. after the server rejected it; otherwise the
+    /// cached refresh token is tried before falling back to the configured
+    /// grant. New tokens are written back to the cache.
+    pub fn access_token(
+        &self,
+        client: &Client,
+        db_conn: Option<&rusqlite::Connection>,
+        force_refresh: bool,
+    ) -> anyhow::Result<String> {
+        let cache_key = self.cache_key();
+        let cached = match db_conn {
+            Some(conn) => fetch_oauth2_token(conn, &cache_key).unwrap_or_else(|e| {
+                warn!(
+                    "failed to read cached oauth2 token, error={}",
+                    redact(&e.to_string())
+                );
+                None
+            }),
+            None => None,
+        };
+
+        if let Some(c) = &cached {
+            if !force_refresh && c.expires_at.is_none_or(|e| e > now()) {
+                return Ok(c.access_token.clone());
+            }
+        }
+
+        let mut token = None;
+        if let Some(rt) = cached.and_then(|c| c.refresh_token) {
+            match self.request_token(
+                client,
+                &[("grant_type", "refresh_token"), ("refresh_token", &rt)],
+            ) {
+                Ok(mut t) => {
+                    // keep using the refresh token if the endpoint did not rotate it
+                    t.refresh_token.get_or_insert(rt);
+                    token = Some(t)
+                }
+                Err(e) => warn!(
+                    "failed to refresh cached oauth2 token, error={}",
+                    redact(&format!("{e:#}"))
+                ),
+            }
+        }
+        let token = match token {
+            Some(t) => t,
+            None => self.request_token(client, &self.grant_params()?)?,
+        };
+
+        if let Some(conn) = db_conn {
+            if let Err(e) = save_oauth2_token(conn, &cache_key, &token) {
+                warn!(
+                    "failed to cache oauth2 token, error={}",
+                    redact(&e.to_string())
+                );
+            }
+        }
+        Ok(token.access_token)
+    }
+
+    fn grant_params<'a>(&'a self) -> anyhow::Result<Vec<(&'a str, &'a str)>> {
+        let required = |field: &'a Option<String>, name: &str| -> anyhow::Result<&'a str> {
+            field.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("oauth2 {:?} grant requires {name}", self.grant_type)
+            })
+        };
+        Ok(match self.grant_type {
+            GrantType::ClientCredentials => vec![("grant_type", "client_credentials")],
+            GrantType::Password => vec![
+                ("grant_type", "password"),
+                ("username", required(&self.username, "username")?),
+                ("password", required(&self.password, "password")?),
+            ],
+            GrantType::RefreshToken => vec![
+                ("grant_type", "refresh_token"),
+                (
+                    "refresh_token",
+                    required(&self.refresh_token, "refresh_token")?,
+                ),
+            ],
+        })
+    }
+
+    fn request_token(
+        &self,
+        client: &Client,
+        params: &[(&str, &str)],
+    ) -> anyhow::Result<CachedToken> {
+        info!("requesting oauth2 token from {}", redact(&self.token_url));
+        let mut form = params.to_vec();
+        if let Some(s) = &self.scope {
+            form.push(("scope", s));
+        }
+        let mut req = client.post(&self.token_url);
+        // Confidential clients authenticate with Basic auth, public clients
+        // only identify themselves in the body
+        match &self.client_secret {
+            Some(secret) => req = req.basic_auth(&self.client_id, Some(secret)),
+            None => form.push(("client_id", &self.client_id)),
+        }
+
+        let resp = req
+            .form(&form)
+            .send()
+            .with_context(|| format!("failed to reach token endpoint={}", self.token_url))?;
+        let status = resp.status();
+        let body = resp.text()?;
+        if !status.is_success() {
+            anyhow::bail!("token endpoint returned status={status}, body={body}");
+        }
+        let token: TokenResponse =
+            serde_json::from_str(&body).with_context(|| "failed to parse token response")?;
+        Ok(CachedToken {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token.or_else(|| self.refresh_token.clone()),
+            expires_at: token.expires_in.map(|e| now() + e - EXPIRY_SKEW_SECS),
+        })
+    }
+
+    fn cache_key(&self) -> String {
+        format!(
+            "{}|{}|{:?}|{}|{}",
+            self.token_url,
+            self.client_id,
+            self.grant_type,
+            self.scope.as_deref().unwrap_or(""),
+            self.username.as_deref().unwrap_or("")
+        )
+    }
+}
+
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{header, serve};
+
+    fn config(grant_type: GrantType, token_url: &str) -> OAuth2Config {
+        OAuth2Config {
+            token_url: format!("{token_url}/token"),
+            grant_type,
+            client_id: "client".to_string(),
+            client_secret: Some("secret".to_string()),
+            scope: Some("read write".to_string()),
+            username: None,
+            password: None,
+            refresh_token: None,
+        }
+    }
+
+    fn token_response(body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn db() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::db::create_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_oauth2_deserialize() {
+        let c: OAuth2Config = serde_json::from_str(
+            r#"{"token_url": "https://auth/token", "grant_type": "password",
+                "client_id": "c", "username": "u", "password": "p"}"#,
+        )
+        .unwrap();
+        assert_eq!(c.grant_type, GrantType::Password);
+        assert_eq!(c.client_secret, None);
+        assert_eq!(c.username.as_deref(), Some("u"));
+    }
+
+    #[test]
+    fn test_oauth2_client_credentials_cached() {
+        let (addr, handle) = serve(1, |_| {
+            token_response(r#"{"access_token": "abc", "token_type": "bearer", "expires_in": 3600}"#)
+        });
+        let conf = config(GrantType::ClientCredentials, &addr);
+        let conn = db();
+        let client = Client::new();
+        assert_eq!(
+            conf.access_token(&client, Some(&conn), false).unwrap(),
+            "abc"
+        );
+        // served from the cache, the server only answers once
+        assert_eq!(
+            conf.access_token(&client, Some(&conn), false).unwrap(),
+            "abc"
+        );
+
+        let raw = handle.join().unwrap().remove(0);
+        assert!(raw.starts_with("POST /token "));
+        assert_eq!(
+            header(&raw, "authorization"),
+            Some("Basic Y2xpZW50OnNlY3JldA==")
+        );
+        assert!(raw.ends_with("\r\n\r\ngrant_type=client_credentials&scope=read+write"));
+    }
+
+    #[test]
+    fn test_oauth2_expired_token_uses_refresh_token() {
+        let (addr, handle) = serve(1, |_| token_response(r#"{"access_token": "new"}"#));
+        let conf = config(GrantType::ClientCredentials, &addr);
+        let conn = db();
+        save_oauth2_token(
+            &conn,
+            &conf.cache_key(),
+            &CachedToken {
+                access_token: "old".to_string(),
+                refresh_token: Some("rt".to_string()),
+                expires_at: Some(now() - 1),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            conf.access_token(&Client::new(), Some(&conn), false)
+                .unwrap(),
+            "new"
+        );
+        let raw = handle.join().unwrap().remove(0);
+        assert!(raw.ends_with("grant_type=refresh_token&refresh_token=rt&scope=read+write"));
+        // the refresh token is kept when the endpoint does not rotate it
+        let cached = fetch_oauth2_token(&conn, &conf.cache_key())
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.access_token, "new");
+        assert_eq!(cached.refresh_token.as_deref(), Some("rt"));
+        assert_eq!(cached.expires_at, None);
+    }
+
+    #[test]
+    fn test_oauth2_password_grant_public_client() {
+        let (addr, handle) = serve(1, |_| token_response(r#"{"access_token": "abc"}"#));
+        let conf = OAuth2Config {
+            client_secret: None,
+            scope: None,
+            username: Some("user".to_string()),
+            password: Some("p&ss".to_string()),
+            ..config(GrantType::Password, &addr)
+        };
+        assert_eq!(
+            conf.access_token(&Client::new(), None, false).unwrap(),
+            "abc"
+        );
+        let raw = handle.join().unwrap().remove(0);
+        assert_eq!(header(&raw, "authorization"), None);
+        assert!(raw.ends_with("grant_type=password&username=user&password=p%26ss&client_id=client"));
+    }
+
+    #[test]
+    fn test_oauth2_errors() {
+        let conf = config(GrantType::Password, "http://127.0.0.1:1");
+        assert_eq!(
+            conf.access_token(&Client::new(), None, false)
+                .unwrap_err()
+                .to_string(),
+            "oauth2 Password grant requires username"
+        );
+
+        let (addr, _handle) = serve(1, |_| {
+            "HTTP/1.1 400 Bad Request\r\nconnection: close\r\ncontent-length: 25\r\n\r\n\
+             {\"error\":\"invalid_grant\"}"
+                .to_string()
+        });
+        let conf = config(GrantType::ClientCredentials, &addr);
+        assert_eq!(
+            conf.access_token(&Client::new(), None, false)
+                .unwrap_err()
+                .to_string(),
+            "token endpoint returned status=400 Bad Request, body={\"error\":\"invalid_grant\"}"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use std::time::{Duration, SystemTime};
+
+use rand::Rng;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::secrets::redact;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryError {
+    /// The connection could not be established.
+    Connect,
+    /// The connect or total timeout from the `client` section ran out.
+    Timeout,
+    /// Any other failure while sending the request or reading the response,
+    /// e.g. the connection was reset.
+    Request,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    // total attempts, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_statuses")]
+    pub statuses: Vec<u16>,
+    #[serde(default = "default_errors")]
+    pub errors: Vec<RetryError>,
+    // seconds before the first retry, multiplied by `multiplier` after each
+    // further attempt and capped at `max_delay`
+    #[serde(default = "default_initial_delay")]
+    pub initial_delay: f64,
+    #[serde(default = "default_max_delay")]
+    pub max_delay: f64,
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    // wait a random time between half and all of the delay
+    #[serde(default = "default_true")]
+    pub jitter: bool,
+    // use the server's Retry-After instead of the backoff, still capped at
+    // `max_delay`
+    #[serde(default = "default_true")]
+    pub respect_retry_after: bool,
+    // also retry POST, PATCH and CONNECT requests
+    #[serde(default)]
+    pub non_idempotent: bool,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_statuses() -> Vec<u16> {
+    vec![429, 502, 503, 504]
+}
+
+fn default_errors() -> Vec<RetryError> {
+    vec![
+        RetryError::Connect,
+        RetryError::Timeout,
+        RetryError::Request,
+    ]
+}
+
+fn default_initial_delay() -> f64 {
+    0.5
+}
+
+fn default_max_delay() -> f64 {
+    30.0
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl RetryConfig {
+    /// Run `send` until it succeeds with a status that is not retried, fails
+    /// in a way that is not retried, or runs out of attempts. Errors that did
+    /// not come from reqwest, e.g. a body file that cannot be opened, are
+    /// returned straight away. Failed attempts, and the one that ends a run of
+    /// retries, are printed to stderr rather than logged, so they show up
+    /// without a logger.
+    pub fn run<F>(&self, mut send: F) -> anyhow::Result<Response>
+    where
+        F: FnMut() -> anyhow::Result<Response>,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            let result = send();
+            let (reason, retry_after) = match &result {
+                Ok(resp) if self.statuses.contains(&resp.status().as_u16()) => (
+                    format!("status={}", resp.status()),
+                    retry_after(resp.headers(), SystemTime::now()),
+                ),
+                Ok(resp) => {
+                    if attempt > 1 {
+                        eprintln!(
+                            "retry: attempt {attempt}/{max_attempts} got status={}",
+                            resp.status()
+                        );
+                    }
+                    return result;
+                }
+                Err(e) if self.retries_error(e) => (redact(&format!("error={e:#}")), None),
+                Err(_) => return result,
+            };
+            if attempt >= max_attempts {
+                eprintln!(
+                    "retry: attempt {attempt}/{max_attempts} failed with {reason}, giving up"
+                );
+                return result;
+            }
+
+            let delay = self.delay(attempt, retry_after);
+            eprintln!(
+                "retry: attempt {attempt}/{max_attempts} failed with {reason}, retrying in \
+                 {delay:?}"
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn retries_error(&self, e: &anyhow::Error) -> bool {
+        let Some(e) = e.downcast_ref::<reqwest::Error>() else {
+            return false;
+        };
+        let kind = if e.is_timeout() {
+            RetryError::Timeout
+        } else if e.is_connect() {
+            RetryError::Connect
+        } else if e.is_request() || e.is_body() || e.is_decode() {
+            RetryError::Request
+        } else {
+            return false;
+        };
+        self.errors.contains(&kind)
+    }
+
+    /// How long to wait after `attempt` failed. Delays too long for a
+    /// `Duration` saturate, and one that is not a number, e.g. a zero
+    /// `initial_delay` times an infinite backoff, is no delay at all.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        // `max` rather than `clamp`, which panics on NaN
+        let max = self.max_delay.max(0.0).min(Duration::MAX.as_secs_f64());
+        if let Some(d) = retry_after.filter(|_| self.respect_retry_after) {
+            return d.min(saturating_secs(max));
+        }
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let mut secs = (self.initial_delay * self.multiplier.powi(exponent)).clamp(0.0, max);
+        if secs.is_nan() {
+            secs = 0.0;
+        }
+        if self.jitter {
+            secs = rand::thread_rng().gen_range(secs / 2.0..=secs);
+        }
+        saturating_secs(secs)
+    }
+}
+
+fn saturating_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(if secs > 0.0 {
+        Duration::MAX
+    } else {
+        Duration::ZERO
+    })
+}
+
+/// Parse a Retry-After header, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::serve;
+    use reqwest::blocking::Client;
+    use reqwest::header::HeaderValue;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            initial_delay: 0.01,
+            jitter: false,
+            ..serde_json::from_str("{}").unwrap()
+        }
+    }
+
+    #[test]
+    fn test_retry_defaults() {
+        let conf: RetryConfig = serde_json::from_str(r#"{"max_attempts": 5}"#).unwrap();
+        assert_eq!(conf.max_attempts, 5);
+        assert_eq!(conf.statuses, vec![429, 502, 503, 504]);
+        assert_eq!(
+            conf.errors,
+            vec![
+                RetryError::Connect,
+                RetryError::Timeout,
+                RetryError::Request
+            ]
+        );
+        assert!(conf.jitter);
+        assert!(conf.respect_retry_after);
+        assert!(!conf.non_idempotent);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let conf = RetryConfig {
+            initial_delay: 1.0,
+            max_delay: 5.0,
+            ..config()
+        };
+        assert_eq!(conf.delay(1, None), Duration::from_secs(1));
+        assert_eq!(conf.delay(2, None), Duration::from_secs(2));
+        assert_eq!(conf.delay(3, None), Duration::from_secs(4));
+        assert_eq!(conf.delay(4, None), Duration::from_secs(5));
+        assert_eq!(
+            conf.delay(1, Some(Duration::from_secs(3))),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            conf.delay(1, Some(Duration::from_secs(60))),
+            Duration::from_secs(5)
+        );
+
+        let conf = RetryConfig {
+            respect_retry_after: false,
+            jitter: true,
+            ..conf
+        };
+        for _ in 0..20 {
+            let d = conf.delay(3, Some(Duration::from_secs(1)));
+            assert!(d >= Duration::from_secs(2) && d <= Duration::from_secs(4));
+        }
+
+        // out of range values saturate rather than panic
+        let conf = RetryConfig {
+            max_delay: 1e20,
+            ..config()
+        };
+        assert_eq!(
+            conf.delay(1, Some(Duration::from_secs(60))),
+            Duration::from_secs(60)
+        );
+        assert_eq!(conf.delay(200, None), Duration::MAX);
+        let conf = RetryConfig {
+            initial_delay: 0.0,
+            multiplier: 1e300,
+            max_delay: f64::INFINITY,
+            jitter: true,
+            ..config()
+        };
+        assert_eq!(conf.delay(3, None), Duration::ZERO);
+        let conf = RetryConfig {
+            max_delay: f64::NAN,
+            ..conf
+        };
+        assert_eq!(conf.delay(3, Some(Duration::from_secs(1))), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers, now), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:30 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(30)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:00:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::ZERO));
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers, now), None);
+    }
+
+    #[test]
+    fn test_retry_statuses() {
+        let (addr, handle) = serve(3, |_| {
+            "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\nretry-after: 0\r\n\
+             content-length: 0\r\n\r\n"
+                .to_string()
+        });
+        let client = Client::new();
+        let resp = config().run(|| Ok(client.get(&addr).send()?)).unwrap();
+        assert_eq!(resp.status(), 503);
+        assert_eq!(handle.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_retry_errors() {
+        let client = Client::new();
+        let mut attempts = 0;
+        let result = config().run(|| {
+            attempts += 1;
+            Ok(client.get("http://127.0.0.1:1").send()?)
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        // only the configured error kinds are retried
+        let conf = RetryConfig {
+            errors: vec![RetryError::Timeout],
+            ..config()
+        };
+        let mut attempts = 0;
+        assert!(conf
+            .run(|| {
+                attempts += 1;
+                Ok(client.get("http://127.0.0.1:1").send()?)
+            })
+            .is_err());
+        assert_eq!(attempts, 1);
+
+        // errors that did not come from sending are never retried
+        let mut attempts = 0;
+        assert!(config()
+            .run(|| {
+                attempts += 1;
+                anyhow::bail!("failed to open body file")
+            })
+            .is_err());
+        assert_eq!(attempts, 1);
+    }
+}
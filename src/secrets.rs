@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use serde_json::{Map, Value};
+
+/// What secrets are replaced with.
+pub const MASK: &str = "********";
+
+/// Shorter values are not masked, they would garble ordinary text. A warning
+/// says so the first time one is seen.
+const MIN_SECRET_LEN: usize = 4;
+
+static WARNED_SHORT: AtomicBool = AtomicBool::new(false);
+
+/// Variables whose name starts with this, ignoring case, are secret.
+pub const SECRET_PREFIX: &str = "secret_";
+
+/// Every secret value seen so far, longest first so a secret that contains
+/// another is masked whole. Kept for the whole process, since secrets can end
+/// up in any error or log line.
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Whether a variable is secret by its name alone.
+pub fn is_secret_name(name: &str) -> bool {
+    name.get(..SECRET_PREFIX.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(SECRET_PREFIX))
+}
+
+/// Remember `value` so it is masked in everything rascal prints or saves.
+pub fn register(value: &str) {
+    if value.len() < MIN_SECRET_LEN {
+        if !value.is_empty() && !WARNED_SHORT.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "warning: a secret shorter than {MIN_SECRET_LEN} characters is not masked in \
+                 the output"
+            );
+        }
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|s| s == value) {
+        secrets.push(value.to_string());
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+/// Register a variable's value, as it is rendered in templates.
+pub fn register_value(value: &Value) {
+    match value {
+        Value::String(s) => register(s),
+        other => register(&other.to_string()),
+    }
+}
+
+/// Replace every registered secret in `text` with `MASK`.
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.read().unwrap();
+    let mut text = text.to_string();
+    for secret in secrets.iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), MASK);
+        }
+    }
+    text
+}
+
+/// Mask secrets in every message of the error chain. Errors without secrets
+/// are returned as they are, so they can still be downcast.
+pub fn redact_error(err: anyhow::Error) -> anyhow::Error {
+    let mut messages: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+    let redacted: Vec<String> = messages.iter().map(|m| redact(m)).collect();
+    if redacted == messages {
+        return err;
+    }
+    messages = redacted;
+    let mut redacted = anyhow::anyhow!(messages.pop().unwrap_or_default());
+    while let Some(message) = messages.pop() {
+        redacted = redacted.context(message);
+    }
+    redacted
+}
+
+/// Register the secret variables in `vars`: those named with `SECRET_PREFIX`
+/// and those declared as `{"secret": value}`, which are unwrapped to `value`.
+pub fn take_secrets(vars: &mut Map<String, Value>) {
+    for (name, value) in vars.iter_mut() {
+        let declared = match value {
+            Value::Object(o) if o.len() == 1 => o.get("secret").cloned(),
+            _ => None,
+        };
+        if let Some(inner) = declared {
+            *value = inner;
+        } else if !is_secret_name(name) {
+            continue;
+        }
+        register_value(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact() {
+        register("hunter2-test-redact");
+        register("hunter2-test-redact-longer");
+        register("abc");
+        assert_eq!(
+            redact("a=hunter2-test-redact b=hunter2-test-redact-longer c=abc"),
+            "a=******** b=******** c=abc"
+        );
+        assert_eq!(redact(""), "");
+    }
+
+    #[test]
+    fn test_redact_error() {
+        register("tok-test-redact-error");
+        let err = anyhow::anyhow!("bad url http://x/?t=tok-test-redact-error")
+            .context("failed to send the request");
+        assert_eq!(
+            format!("{:#}", redact_error(err)),
+            "failed to send the request: bad url http://x/?t=********"
+        );
+
+        // errors without secrets keep their type
+        let err = anyhow::Error::new(std::io::Error::other("nothing to hide"));
+        assert!(redact_error(err).downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn test_take_secrets() {
+        let mut vars = json!({
+            "host": "example.com",
+            "token": {"secret": "tok-test-take-secrets"},
+            "SECRET_pin": 41424344,
+            "nested": {"secret": "a", "other": "b"}
+        });
+        take_secrets(vars.as_object_mut().unwrap());
+        assert_eq!(
+            vars,
+            json!({
+                "host": "example.com",
+                "token": "tok-test-take-secrets",
+                "SECRET_pin": 41424344,
+                "nested": {"secret": "a", "other": "b"}
+            })
+        );
+        assert_eq!(
+            redact("tok-test-take-secrets 41424344 example.com"),
+            "******** ******** example.com"
+        );
+        assert!(is_secret_name("secret_key"));
+        assert!(!is_secret_name("secret"));
+    }
+}
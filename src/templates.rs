@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+
+use base64::prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::{Rng, RngCore};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use tera::{Tera, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Shown by `rascal help templates`.
+pub const HELP: &str = "\
+Request files are tera templates (https://keats.github.io/tera/docs/), rendered
+before they are parsed. Besides tera's own functions and filters, e.g.
+`urlencode`, `lower` or `default`, rascal provides the following.
+
+Variables:
+  env_NAME                  the NAME environment variable
+  arg_NAME                  the NAME argument given with `-k NAME=value`, or
+                            `-k NAME:=json` for a number, boolean, list or object
+  vars.NAME                 collection, environment or `-k` variable NAME
+
+Functions:
+  uuid()                    random UUID, version 4
+  uuid(version=7)           time-ordered UUID, version 7
+  now()                     current UTC time as RFC 3339, e.g. 2024-03-01T12:00:00Z
+  now(format=\"unix\")        seconds since the epoch, or \"unix_millis\", \"rfc3339\"
+                            or a format description like \"[year]-[month]-[day]\"
+  now(offset=\"-1h30m\")      shifted by an offset in w, d, h, m and s units
+  random_int(min=0, max=9)  random integer between min and max, both included
+  random_string(length=16)  random string of `charset` characters: \"alphanumeric\"
+                            (default), \"alpha\", \"numeric\" or \"hex\"
+
+Filters:
+  base64_encode             standard base64, with padding
+  base64_decode             decode standard base64 into UTF-8 text
+  base64url_encode          url-safe base64, without padding
+  base64url_decode          decode url-safe base64 into UTF-8 text
+  sha256                    SHA-256 digest, hex encoded
+  hmac_sha256(key=\"...\")    HMAC-SHA256 with `key`, hex encoded
+                            both digests take encoding=\"base64\" as well
+  json_escape               escape a value for use inside a JSON string
+  json_encode               a value as JSON, e.g. an object from `-k NAME:=json`
+  raw                       a string as it is, without escaping
+
+Escaping:
+  In .json files every string value is escaped for use inside a JSON string,
+  so quotes, backslashes or newlines in a value cannot break the request or
+  change its structure. Numbers and booleans are written as they are. Use
+  `raw` to place a string without escaping, e.g. a JSON fragment. YAML and
+  TOML files are not escaped, use `json_escape` inside double quoted strings.
+
+Examples:
+  \"idempotency-key\": \"{{ uuid() }}\"
+  \"x-date\": \"{{ now(format='[year]-[month]-[day]', offset='-1d') }}\"
+  \"x-signature\": \"{{ arg_payload | hmac_sha256(key=vars.secret_key) }}\"
+  \"retries\": {{ arg_retries }}, \"tags\": {{ arg_tags | json_encode }}
+";
+
+/// Register rascal's template functions and filters.
+pub fn register(tera: &mut Tera) {
+    tera.register_function("uuid", uuid);
+    tera.register_function("now", now);
+    tera.register_function("random_int", random_int);
+    tera.register_function("random_string", random_string);
+    tera.register_filter("base64_encode", base64_encode);
+    tera.register_filter("base64_decode", base64_decode);
+    tera.register_filter("base64url_encode", base64url_encode);
+    tera.register_filter("base64url_decode", base64url_decode);
+    tera.register_filter("sha256", sha256);
+    tera.register_filter("hmac_sha256", hmac_sha256);
+    tera.register_filter("json_escape", Safe(json_escape));
+    tera.register_filter("json_encode", Safe(json_encode));
+    tera.register_filter("raw", Safe(|value, _| Ok(value.clone())));
+}
+
+/// Escape `text` for use inside a JSON string, the escape function of JSON
+/// templates.
+pub fn escape_json(text: &str) -> String {
+    let quoted = Value::String(text.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// A filter whose output is never escaped, because it is escaped already or
+/// asked to be left alone.
+struct Safe(fn(&Value, &HashMap<String, Value>) -> tera::Result<Value>);
+
+impl tera::Filter for Safe {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        (self.0)(value, args)
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// The optional argument `name` of a function or filter.
+fn arg<T: DeserializeOwned>(
+    args: &HashMap<String, Value>,
+    function: &str,
+    name: &str,
+) -> tera::Result<Option<T>> {
+    args.get(name)
+        .map(|v| {
+            serde_json::from_value(v.clone()).map_err(|e| {
+                tera::Error::msg(format!("invalid `{name}` for `{function}`, error={e}"))
+            })
+        })
+        .transpose()
+}
+
+/// The value a filter was applied to, as a string. Numbers and booleans are
+/// used as written.
+fn text(value: &Value, filter: &str) -> tera::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        _ => Err(tera::Error::msg(format!(
+            "`{filter}` needs a string, got {value}"
+        ))),
+    }
+}
+
+fn uuid(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let version = arg::<u8>(args, "uuid", "version")?.unwrap_or(4);
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    match version {
+        4 => {}
+        7 => {
+            let millis = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+            bytes[..6].copy_from_slice(&(millis as u64).to_be_bytes()[2..]);
+        }
+        _ => {
+            return Err(tera::Error::msg(format!(
+                "unsupported uuid version={version}, use 4 or 7"
+            )))
+        }
+    }
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    // RFC 4122 variant
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    Ok(Value::String(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )))
+}
+
+fn now(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let format = arg::<String>(args, "now", "format")?;
+    let offset = arg::<String>(args, "now", "offset")?;
+    format_time(
+        OffsetDateTime::now_utc(),
+        format.as_deref(),
+        offset.as_deref(),
+    )
+}
+
+fn format_time(
+    time: OffsetDateTime,
+    format: Option<&str>,
+    offset: Option<&str>,
+) -> tera::Result<Value> {
+    let time = match offset {
+        Some(o) => time
+            .checked_add(parse_offset(o)?)
+            .ok_or_else(|| out_of_range(o))?,
+        None => time,
+    };
+    let formatted = match format.unwrap_or("rfc3339") {
+        "unix" => return Ok(Value::from(time.unix_timestamp())),
+        "unix_millis" => {
+            return Ok(Value::from(
+                (time.unix_timestamp_nanos() / 1_000_000) as i64,
+            ))
+        }
+        "rfc3339" => time.format(&Rfc3339),
+        description => {
+            let description = time::format_description::parse(description)
+                .map_err(|e| tera::Error::msg(format!("invalid format for `now`, error={e}")))?;
+            time.format(&description)
+        }
+    };
+    formatted
+        .map(Value::String)
+        .map_err(|e| tera::Error::msg(format!("failed to format time, error={e}")))
+}
+
+fn out_of_range(offset: &str) -> tera::Error {
+    tera::Error::msg(format!("offset={offset:?} for `now` is out of range"))
+}
+
+/// Parse an offset like `-1h30m`.
+fn parse_offset(offset: &str) -> tera::Result<time::Duration> {
+    let invalid = || {
+        tera::Error::msg(format!(
+            "invalid offset={offset:?} for `now`, expected e.g. \"+1d\" or \"-1h30m\""
+        ))
+    };
+    let (negative, mut rest) = match offset.trim().as_bytes().first() {
+        Some(b'-') => (true, &offset.trim()[1..]),
+        Some(b'+') => (false, &offset.trim()[1..]),
+        _ => (false, offset.trim()),
+    };
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut secs = 0i64;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        if digits == 0 {
+            return Err(invalid());
+        }
+        let amount: i64 = rest[..digits].parse().map_err(|_| out_of_range(offset))?;
+        let unit = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            Some('w') => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        secs = amount
+            .checked_mul(unit)
+            .and_then(|s| secs.checked_add(s))
+            .ok_or_else(|| out_of_range(offset))?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(time::Duration::seconds(if negative { -secs } else { secs }))
+}
+
+fn random_int(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let min = arg::<i64>(args, "random_int", "min")?.unwrap_or(0);
+    let max = arg::<i64>(args, "random_int", "max")?.unwrap_or(i32::MAX.into());
+    if min > max {
+        return Err(tera::Error::msg(format!(
+            "`random_int` needs min <= max, got min={min} max={max}"
+        )));
+    }
+    Ok(Value::from(rand::thread_rng().gen_range(min..=max)))
+}
+
+fn random_string(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let length = arg::<usize>(args, "random_string", "length")?.unwrap_or(16);
+    let charset = arg::<String>(args, "random_string", "charset")?;
+    let mut rng = rand::thread_rng();
+    let chars: &[u8] = match charset.as_deref().unwrap_or("alphanumeric") {
+        "alphanumeric" => return Ok(Value::String(Alphanumeric.sample_string(&mut rng, length))),
+        "alpha" => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+        "numeric" => b"0123456789",
+        "hex" => b"0123456789abcdef",
+        other => {
+            return Err(tera::Error::msg(format!(
+                "unknown charset={other} for `random_string`, use alphanumeric, alpha, numeric \
+                 or hex"
+            )))
+        }
+    };
+    Ok(Value::String(
+        (0..length)
+            .map(|_| chars[rng.gen_range(0..chars.len())] as char)
+            .collect(),
+    ))
+}
+
+fn base64_encode(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(
+        BASE64_STANDARD.encode(text(value, "base64_encode")?),
+    ))
+}
+
+fn base64_decode(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    decode(&BASE64_STANDARD, value, "base64_decode")
+}
+
+fn base64url_encode(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(
+        BASE64_URL_SAFE_NO_PAD.encode(text(value, "base64url_encode")?),
+    ))
+}
+
+fn base64url_decode(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    decode(&BASE64_URL_SAFE_NO_PAD, value, "base64url_decode")
+}
+
+fn decode(engine: &impl Engine, value: &Value, filter: &str) -> tera::Result<Value> {
+    let bytes = engine
+        .decode(text(value, filter)?)
+        .map_err(|e| tera::Error::msg(format!("`{filter}` got invalid base64, error={e}")))?;
+    String::from_utf8(bytes)
+        .map(Value::String)
+        .map_err(|_| tera::Error::msg(format!("`{filter}` decoded to something other than text")))
+}
+
+/// Encode a digest as the `encoding` argument asks, hex by default.
+fn encode_digest(
+    digest: &[u8],
+    args: &HashMap<String, Value>,
+    filter: &str,
+) -> tera::Result<Value> {
+    match arg::<String>(args, filter, "encoding")?.as_deref() {
+        None | Some("hex") => Ok(Value::String(hex::encode(digest))),
+        Some("base64") => Ok(Value::String(BASE64_STANDARD.encode(digest))),
+        Some(other) => Err(tera::Error::msg(format!(
+            "unknown encoding={other} for `{filter}`, use hex or base64"
+        ))),
+    }
+}
+
+fn sha256(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let digest = Sha256::digest(text(value, "sha256")?.as_bytes());
+    encode_digest(&digest, args, "sha256")
+}
+
+fn hmac_sha256(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let key = arg::<String>(args, "hmac_sha256", "key")?
+        .ok_or_else(|| tera::Error::msg("`hmac_sha256` needs a `key`"))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .map_err(|e| tera::Error::msg(format!("invalid key for `hmac_sha256`, error={e}")))?;
+    mac.update(text(value, "hmac_sha256")?.as_bytes());
+    encode_digest(&mac.finalize().into_bytes(), args, "hmac_sha256")
+}
+
+fn json_escape(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(escape_json(&text(value, "json_escape")?)))
+}
+
+/// Like tera's own `json_encode`, but its output is valid JSON already so it
+/// is not escaped.
+fn json_encode(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let encoded = if arg::<bool>(args, "json_encode", "pretty")?.unwrap_or(false) {
+        serde_json::to_string_pretty(value)?
+    } else {
+        serde_json::to_string(value)?
+    };
+    Ok(Value::String(encoded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(template: &str) -> tera::Result<String> {
+        let mut tera = Tera::default();
+        register(&mut tera);
+        tera.render_str(template, &tera::Context::new())
+    }
+
+    #[test]
+    fn test_uuid() {
+        let v4 = render("{{ uuid() }}").unwrap();
+        assert_eq!(v4.len(), 36);
+        assert_eq!(v4.as_bytes()[14], b'4');
+        assert!(matches!(v4.as_bytes()[19], b'8' | b'9' | b'a' | b'b'));
+        assert_ne!(v4, render("{{ uuid() }}").unwrap());
+
+        let v7 = render("{{ uuid(version=7) }}").unwrap();
+        assert_eq!(v7.as_bytes()[14], b'7');
+        // the first 48 bits are the unix time in milliseconds
+        let millis = i64::from_str_radix(&v7[..13].replace('-', ""), 16).unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp() * 1000;
+        assert!((now - millis).abs() < 60_000);
+
+        assert!(render("{{ uuid(version=1) }}").is_err());
+    }
+
+    #[test]
+    fn test_now() {
+        let time = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let fmt = |format: Option<&str>, offset: Option<&str>| format_time(time, format, offset);
+        assert_eq!(fmt(None, None).unwrap(), "2023-11-14T22:13:20Z");
+        assert_eq!(fmt(Some("unix"), None).unwrap(), 1_700_000_000);
+        assert_eq!(
+            fmt(Some("unix_millis"), None).unwrap(),
+            1_700_000_000_000i64
+        );
+        assert_eq!(
+            fmt(
+                Some("[year]-[month]-[day] [hour]:[minute]"),
+                Some("-1d1h30m")
+            )
+            .unwrap(),
+            "2023-11-13 20:43"
+        );
+        assert_eq!(fmt(Some("unix"), Some("+1w")).unwrap(), 1_700_604_800);
+        assert!(fmt(Some("[nope]"), None).is_err());
+        assert!(fmt(None, Some("1x")).is_err());
+        assert!(fmt(None, Some("-")).is_err());
+        assert!(fmt(None, Some("h")).is_err());
+        // too far to represent, as a number of seconds or as a date
+        for offset in [
+            "99999999999999w",
+            "9999999999w",
+            "-9999999999w",
+            "99999999999999999999s",
+        ] {
+            let err = fmt(None, Some(offset)).unwrap_err();
+            assert!(err.to_string().ends_with("is out of range"), "{err}");
+        }
+
+        let rendered: i64 = render("{{ now(format=\"unix\") }}")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((rendered - OffsetDateTime::now_utc().unix_timestamp()).abs() < 60);
+    }
+
+    #[test]
+    fn test_random() {
+        for _ in 0..20 {
+            let n: i64 = render("{{ random_int(min=-2, max=2) }}")
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((-2..=2).contains(&n));
+        }
+        assert!(render("{{ random_int(min=3, max=2) }}").is_err());
+
+        let s = render("{{ random_string() }}").unwrap();
+        assert_eq!(s.len(), 16);
+        assert!(s.chars().all(|c| c.is_ascii_alphanumeric()));
+        let s = render("{{ random_string(length=32, charset=\"hex\") }}").unwrap();
+        assert_eq!(s.len(), 32);
+        assert!(s.chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(render("{{ random_string(charset=\"emoji\") }}").is_err());
+    }
+
+    #[test]
+    fn test_encoding_filters() {
+        assert_eq!(
+            render("{{ \"user:pass?\" | base64_encode }}").unwrap(),
+            "dXNlcjpwYXNzPw=="
+        );
+        assert_eq!(
+            render("{{ \"user:pass?\" | base64url_encode }}").unwrap(),
+            "dXNlcjpwYXNzPw"
+        );
+        assert_eq!(
+            render("{{ \"dXNlcjpwYXNzPw==\" | base64_decode }}").unwrap(),
+            "user:pass?"
+        );
+        assert_eq!(
+            render("{{ \"dXNlcjpwYXNzPw\" | base64url_decode }}").unwrap(),
+            "user:pass?"
+        );
+        assert!(render("{{ \"%%%\" | base64_decode }}").is_err());
+        assert_eq!(
+            render("{{ 'say \"hi\"\\n' | json_escape }}").unwrap(),
+            "say \\\"hi\\\"\\\\n"
+        );
+        assert_eq!(render("{{ 42 | base64_encode }}").unwrap(), "NDI=");
+    }
+
+    #[test]
+    fn test_digest_filters() {
+        assert_eq!(
+            render("{{ \"abc\" | sha256 }}").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // RFC 4231, test case 2
+        assert_eq!(
+            render("{{ \"what do ya want for nothing?\" | hmac_sha256(key=\"Jefe\") }}").unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            render("{{ \"abc\" | sha256(encoding=\"base64\") }}").unwrap(),
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+        assert!(render("{{ \"abc\" | hmac_sha256 }}").is_err());
+        assert!(render("{{ \"abc\" | sha256(encoding=\"rot13\") }}").is_err());
+    }
+
+    #[test]
+    fn test_json_escaping() {
+        let mut tera = Tera::default();
+        register(&mut tera);
+        tera.autoescape_on(vec![".json"]);
+        tera.set_escape_fn(escape_json);
+        let mut context = tera::Context::new();
+        context.insert("name", "a\"b\\c\nd");
+        context.insert("count", &3);
+        context.insert("tags", &serde_json::json!({"x": ["y"]}));
+        let mut render = |template: &str| {
+            tera.add_raw_template("t.json", template).unwrap();
+            tera.render("t.json", &context).unwrap()
+        };
+
+        assert_eq!(render("\"{{ name }}\""), r#""a\"b\\c\nd""#);
+        assert_eq!(render("\"{{ name | upper }}\""), r#""A\"B\\C\nD""#);
+        // escaped once, whichever filter does it
+        assert_eq!(render("\"{{ name | json_escape }}\""), r#""a\"b\\c\nd""#);
+        assert_eq!(render("{{ name | raw }}"), "a\"b\\c\nd");
+        assert_eq!(render("{{ name | safe }}"), "a\"b\\c\nd");
+        assert_eq!(render("{{ count }}"), "3");
+        assert_eq!(render("{{ tags | json_encode }}"), r#"{"x":["y"]}"#);
+        assert_eq!(render("{{ name | json_encode }}"), r#""a\"b\\c\nd""#);
+    }
+}
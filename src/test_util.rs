@@ -0,0 +1,143 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+
+pub const OK: &str = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+
+/// Spawn a server on an ephemeral port that answers `count` requests, one per
+/// connection, with whatever `handler` returns for the raw request. Hands
+/// back every raw request it received, in order.
+pub fn serve<F>(count: usize, handler: F) -> (String, JoinHandle<Vec<String>>)
+where
+    F: Fn(&str) -> String + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for _ in 0..count {
+            let (mut stream, _) = listener.accept().unwrap();
+            let raw = read_request(&mut BufReader::new(stream.try_clone().unwrap()));
+            let _ = stream.write_all(handler(&raw).as_bytes());
+            requests.push(raw);
+        }
+        requests
+    });
+    (addr, handle)
+}
+
+/// Spawn a server that answers a single request with `response` and hands
+/// back the raw request it received.
+pub fn serve_once(response: &'static str) -> (String, JoinHandle<String>) {
+    let (addr, handle) = serve(1, move |_| response.to_string());
+    let handle = std::thread::spawn(move || handle.join().unwrap().remove(0));
+    (addr, handle)
+}
+
+/// Path of a file in the TLS test fixtures. The CA signed both the server
+/// certificate, valid for 127.0.0.1 and localhost, and the client certificate.
+pub fn tls_fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/tls/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Spawn an https server that answers a single connection with `response`.
+/// With `require_client_cert` the handshake fails unless the client presents
+/// a certificate signed by the fixture CA. Hands back the raw request, or
+/// None if the handshake failed.
+pub fn serve_tls(
+    require_client_cert: bool,
+    response: &'static str,
+) -> (String, JoinHandle<Option<String>>) {
+    let (addr, handle) = serve_tls_alpn(require_client_cert, None, response);
+    let handle = std::thread::spawn(move || handle.join().unwrap().map(|(raw, _)| raw));
+    (addr, handle)
+}
+
+/// The raw request a TLS server received and the ALPN protocol it picked.
+pub type TlsExchange = (String, Option<String>);
+
+/// Like `serve_tls`, but also picks `alpn` during the handshake when the
+/// client offers it, and hands back the protocol that was negotiated.
+pub fn serve_tls_alpn(
+    require_client_cert: bool,
+    alpn: Option<&'static str>,
+    response: &'static str,
+) -> (String, JoinHandle<Option<TlsExchange>>) {
+    use openssl::ssl::{AlpnError, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(tls_fixture("server.key"), SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(tls_fixture("server.pem"))
+        .unwrap();
+    if require_client_cert {
+        acceptor.set_ca_file(tls_fixture("ca.pem")).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    if let Some(protocol) = alpn {
+        acceptor.set_alpn_select_callback(move |_, offered| {
+            // offered protocols are length prefixed
+            let mut rest = offered;
+            while let Some((&len, tail)) = rest.split_first() {
+                let (name, tail) = tail.split_at(len as usize);
+                if name == protocol.as_bytes() {
+                    return Ok(name);
+                }
+                rest = tail;
+            }
+            Err(AlpnError::NOACK)
+        });
+    }
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("https://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = acceptor.accept(stream).ok()?;
+        let negotiated = stream
+            .ssl()
+            .selected_alpn_protocol()
+            .map(|p| String::from_utf8_lossy(p).to_string());
+        let raw = read_request(&mut BufReader::new(&mut stream));
+        let _ = stream.write_all(response.as_bytes());
+        Some((raw, negotiated))
+    });
+    (addr, handle)
+}
+
+/// Pull the value of header `name` out of a raw request.
+pub fn header<'a>(raw: &'a str, name: &str) -> Option<&'a str> {
+    raw.lines()
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim())
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> String {
+    let mut raw = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        // the client may hang up without sending anything
+        if reader.read_line(&mut line).is_err() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                content_length = v.trim().parse().unwrap();
+            }
+        }
+        raw.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    raw.push_str(&String::from_utf8_lossy(&body));
+    raw
+}
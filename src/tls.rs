@@ -0,0 +1,251 @@
+use std::path::Path;
+
+use anyhow::Context;
+use reqwest::blocking::ClientBuilder;
+use reqwest::tls::{Certificate, Identity, Version};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum TlsVersion {
+    #[serde(rename = "1.0")]
+    V1_0,
+    #[serde(rename = "1.1")]
+    V1_1,
+    #[serde(rename = "1.2")]
+    V1_2,
+    #[serde(rename = "1.3")]
+    V1_3,
+}
+
+impl From<TlsVersion> for Version {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::V1_0 => Version::TLS_1_0,
+            TlsVersion::V1_1 => Version::TLS_1_1,
+            TlsVersion::V1_2 => Version::TLS_1_2,
+            TlsVersion::V1_3 => Version::TLS_1_3,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    // PEM bundle trusted on top of the system roots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
+    // PEM client certificate, requires `client_key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    // PEM (PKCS#8) private key for `client_cert`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    // PKCS#12 archive holding both the client certificate and key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs12: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs12_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_version: Option<TlsVersion>,
+    // skip certificate and hostname verification entirely
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure: Option<bool>,
+}
+
+impl TlsConfig {
+    /// Values that must never be printed.
+    pub fn secrets(&self) -> Vec<String> {
+        self.pkcs12_password.iter().cloned().collect()
+    }
+
+    /// Resolve relative certificate and key paths against `dir`.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        let paths = [
+            &mut self.ca_file,
+            &mut self.client_cert,
+            &mut self.client_key,
+            &mut self.pkcs12,
+        ];
+        for path in paths.into_iter().flatten() {
+            if Path::new(path.as_str()).is_relative() {
+                *path = dir.join(&path).to_string_lossy().to_string();
+            }
+        }
+    }
+
+    /// Apply the TLS settings to the client. Certificate and key files are
+    /// read here, so a missing or malformed file fails before anything is sent.
+    pub fn configure(&self, mut client_builder: ClientBuilder) -> anyhow::Result<ClientBuilder> {
+        if let Some(ca) = &self.ca_file {
+            let pem = std::fs::read(ca).with_context(|| format!("failed to read ca_file={ca}"))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("invalid PEM in ca_file={ca}"))?;
+            if certs.is_empty() {
+                anyhow::bail!("no certificates found in ca_file={ca}");
+            }
+            for cert in certs {
+                client_builder = client_builder.add_root_certificate(cert);
+            }
+        }
+
+        let identity = match (&self.client_cert, &self.client_key, &self.pkcs12) {
+            (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+                anyhow::bail!("tls client_cert/client_key and pkcs12 cannot both be set")
+            }
+            (Some(cert), Some(key), None) => {
+                let cert_pem = std::fs::read(cert)
+                    .with_context(|| format!("failed to read client_cert={cert}"))?;
+                let key_pem = std::fs::read(key)
+                    .with_context(|| format!("failed to read client_key={key}"))?;
+                Some(
+                    Identity::from_pkcs8_pem(&cert_pem, &key_pem).with_context(|| {
+                        format!("invalid client_cert={cert} or client_key={key}")
+                    })?,
+                )
+            }
+            (Some(_), None, None) | (None, Some(_), None) => {
+                anyhow::bail!("tls client_cert and client_key must be set together")
+            }
+            (None, None, Some(p12)) => {
+                let der =
+                    std::fs::read(p12).with_context(|| format!("failed to read pkcs12={p12}"))?;
+                Some(
+                    Identity::from_pkcs12_der(&der, self.pkcs12_password.as_deref().unwrap_or(""))
+                        .with_context(|| format!("invalid pkcs12={p12} or pkcs12_password"))?,
+                )
+            }
+            (None, None, None) => None,
+        };
+        if let Some(id) = identity {
+            client_builder = client_builder.identity(id);
+        }
+
+        if let Some(v) = self.min_version {
+            client_builder = client_builder.min_tls_version(v.into());
+        }
+        if self.insecure == Some(true) {
+            // printed directly rather than logged, so it cannot be filtered out
+            eprintln!(
+                "warning: tls.insecure is set, TLS certificates and hostnames are not \
+                 verified. Anyone on the network can read and modify this request."
+            );
+            client_builder = client_builder.danger_accept_invalid_certs(true);
+        }
+        Ok(client_builder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{serve_tls, tls_fixture, OK};
+    use reqwest::blocking::Client;
+
+    fn get(conf: &TlsConfig, addr: &str) -> anyhow::Result<reqwest::StatusCode> {
+        let client = conf.configure(Client::builder())?.build()?;
+        Ok(client.get(addr).send()?.status())
+    }
+
+    fn trusted() -> TlsConfig {
+        TlsConfig {
+            ca_file: Some(tls_fixture("ca.pem")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tls_deserialize() {
+        let conf: TlsConfig = serde_json::from_str(
+            r#"{"ca_file": "ca.pem", "min_version": "1.2", "insecure": false}"#,
+        )
+        .unwrap();
+        assert_eq!(conf.min_version, Some(TlsVersion::V1_2));
+        assert_eq!(conf.insecure, Some(false));
+        assert_eq!(conf.pkcs12, None);
+        assert!(serde_json::from_str::<TlsConfig>(r#"{"min_version": "1.4"}"#).is_err());
+    }
+
+    #[test]
+    fn test_tls_ca_file() {
+        let (addr, handle) = serve_tls(false, OK);
+        assert_eq!(get(&trusted(), &addr).unwrap(), 200);
+        assert!(handle.join().unwrap().unwrap().starts_with("GET / "));
+
+        // the self-signed CA is not trusted by default
+        let (addr, handle) = serve_tls(false, OK);
+        assert!(get(&TlsConfig::default(), &addr).is_err());
+        assert_eq!(handle.join().unwrap(), None);
+    }
+
+    #[test]
+    fn test_tls_insecure() {
+        let (addr, handle) = serve_tls(false, OK);
+        let conf = TlsConfig {
+            insecure: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(get(&conf, &addr).unwrap(), 200);
+        assert!(handle.join().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_tls_client_cert_pem() {
+        let (addr, handle) = serve_tls(true, OK);
+        let conf = TlsConfig {
+            client_cert: Some(tls_fixture("client.pem")),
+            client_key: Some(tls_fixture("client.key")),
+            min_version: Some(TlsVersion::V1_2),
+            ..trusted()
+        };
+        assert_eq!(get(&conf, &addr).unwrap(), 200);
+        assert!(handle.join().unwrap().is_some());
+
+        let (addr, handle) = serve_tls(true, OK);
+        assert!(get(&trusted(), &addr).is_err());
+        assert_eq!(handle.join().unwrap(), None);
+    }
+
+    #[test]
+    fn test_tls_client_cert_pkcs12() {
+        let (addr, handle) = serve_tls(true, OK);
+        let conf = TlsConfig {
+            pkcs12: Some(tls_fixture("client.p12")),
+            pkcs12_password: Some("rascal".to_string()),
+            ..trusted()
+        };
+        assert_eq!(get(&conf, &addr).unwrap(), 200);
+        assert!(handle.join().unwrap().is_some());
+
+        let conf = TlsConfig {
+            pkcs12_password: Some("wrong".to_string()),
+            ..conf
+        };
+        assert!(conf
+            .configure(Client::builder())
+            .unwrap_err()
+            .to_string()
+            .starts_with("invalid pkcs12="));
+    }
+
+    #[test]
+    fn test_tls_config_errors() {
+        let conf = TlsConfig {
+            client_cert: Some(tls_fixture("client.pem")),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.configure(Client::builder()).unwrap_err().to_string(),
+            "tls client_cert and client_key must be set together"
+        );
+
+        let conf = TlsConfig {
+            ca_file: Some("/does/not/exist.pem".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.configure(Client::builder()).unwrap_err().to_string(),
+            "failed to read ca_file=/does/not/exist.pem"
+        );
+    }
+}
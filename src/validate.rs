@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use serde_path_to_error::Segment;
+
+use crate::collection::CONFIG_FILES;
+use crate::diagnostics::SourceMap;
+use crate::executer::{load_collection, render_template_as};
+use crate::extends::{resolve_extends, EXTENDS};
+use crate::parser::{parse_value, Request, RequestFormat};
+
+/// Value given to template variables that are neither set in the
+/// environment nor passed with `-k`. A number is valid JSON or TOML both in a
+/// string and where a value goes, e.g. `"port": {{ arg_port }}`. In YAML an
+/// unquoted number is not a string, and any word parses, so a word is used.
+fn placeholder(format: RequestFormat) -> Value {
+    match format {
+        RequestFormat::Json | RequestFormat::Toml => Value::from(0),
+        RequestFormat::Yaml => Value::from("placeholder"),
+    }
+}
+
+/// Give up on a file after this many problems, in case removing the
+/// offending values does not converge.
+const MAX_PROBLEMS: usize = 50;
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(l) => write!(f, "{}:{l}: {}", self.file, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Collect the request files under `paths`. Directories are searched
+/// recursively for json, yaml and toml files, other than collection configs.
+pub fn request_files(paths: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        let mut entries = std::fs::read_dir(dir)?
+            .map(|e| Ok(e?.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+        for path in entries {
+            let is_config = path
+                .file_name()
+                .is_some_and(|n| CONFIG_FILES.iter().any(|c| n == *c));
+            if path.is_dir() {
+                walk(&path, files)?;
+            } else if !is_config
+                && matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("json" | "yaml" | "yml" | "toml")
+                )
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for p in paths {
+        let path = PathBuf::from(p);
+        if path.is_dir() {
+            walk(&path, &mut files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Render the template in `file` and check it strictly against `Request`,
+/// returning every problem found.
+pub fn validate_file(
+    file: &Path,
+    format: Option<RequestFormat>,
+    env: Option<&str>,
+    kwarg_map: &HashMap<String, Value>,
+) -> Vec<Problem> {
+    let name = file.display().to_string();
+    let problem = |line: Option<usize>, message: String| Problem {
+        file: name.clone(),
+        line,
+        message,
+    };
+
+    let template = match std::fs::read_to_string(file) {
+        Ok(t) => t,
+        Err(e) => return vec![problem(None, format!("failed to read file, error={e}"))],
+    };
+    let render_config = |file: &str, template: &str, context: &tera::Context| {
+        render_with_placeholders(file, template, RequestFormat::from_path(file), context)
+    };
+    let (config, context) = match load_collection(&name, env, kwarg_map, &render_config) {
+        Ok(c) => c,
+        Err(e) => return vec![problem(None, format!("{e:#}"))],
+    };
+    let format = format.unwrap_or_else(|| RequestFormat::from_path(&name));
+    let render = |file: &str, template: &str| {
+        render_with_placeholders(file, template, RequestFormat::from_path(file), &context)
+    };
+    let rendered = match render_with_placeholders(&name, &template, format, &context) {
+        Ok(r) => r,
+        Err(e) => return vec![problem(None, format!("{e:#}"))],
+    };
+
+    // Lines are reported in the template, not the rendered output
+    let source_map = SourceMap::new(&template, &rendered);
+    let mut value = match parse_value(&rendered, format) {
+        Ok(v) => v,
+        Err(e) => {
+            let line = e.line.map(|l| source_map.locate(l, None).0);
+            return vec![problem(line, e.message)];
+        }
+    };
+
+    // Check the request as it is sent, merged over the requests it extends
+    // and the collection defaults
+    if value.get(EXTENDS).is_some() {
+        let line = locate(
+            &rendered,
+            &[Segment::Map {
+                key: EXTENDS.to_string(),
+            }],
+        );
+        value = match resolve_extends(&name, value, &render) {
+            Ok(v) => v,
+            Err(e) => {
+                return vec![problem(
+                    line.map(|l| source_map.locate(l, None).0),
+                    format!("{e:#}"),
+                )]
+            }
+        };
+    }
+
+    if let Some(config) = config {
+        value = match config.apply(value) {
+            Ok(v) => v,
+            Err(e) => return vec![problem(None, format!("{e:#}"))],
+        };
+    }
+
+    check_request(&mut value)
+        .into_iter()
+        .map(|(path, message)| {
+            let line = locate(&rendered, &path).map(|l| source_map.locate(l, None).0);
+            problem(line, message)
+        })
+        .collect()
+}
+
+/// Render `template` with a `placeholder` standing in for the variables it
+/// uses that `context` does not set.
+fn render_with_placeholders(
+    file: &str,
+    template: &str,
+    format: RequestFormat,
+    context: &tera::Context,
+) -> anyhow::Result<String> {
+    let mut context = context.clone();
+    let mut vars = match context.get("vars") {
+        Some(Value::Object(vars)) => vars.clone(),
+        _ => Map::new(),
+    };
+    let placeholder = placeholder(format);
+    for var in template_variables(template) {
+        if let Some(name) = var.strip_prefix("vars.") {
+            vars.entry(name).or_insert_with(|| placeholder.clone());
+        } else if !context.contains_key(&var) {
+            context.insert(var, &placeholder);
+        }
+    }
+    context.insert("vars", &vars);
+    render_template_as(file, template, format, &context)
+}
+
+/// Deserialize `value` as a `Request` over and over, removing whatever the
+/// last error pointed at, so every problem is found rather than the first.
+fn check_request(value: &mut Value) -> Vec<(Vec<Segment>, String)> {
+    let mut problems = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    while problems.len() < MAX_PROBLEMS {
+        let err = match serde_path_to_error::deserialize::<_, Request>(&*value) {
+            Ok(_) => break,
+            Err(e) => e,
+        };
+        let mut path: Vec<Segment> = err.path().iter().cloned().collect();
+        let mut message = err.inner().to_string();
+
+        // The url object sits behind an untagged enum, which only reports
+        // that nothing matched. Check it directly for a useful error.
+        if message.contains("untagged enum StringOrUrl") {
+            if let Some(url) = get(value, &path).filter(|v| v.is_object()) {
+                if let Err(e) = serde_path_to_error::deserialize::<_, crate::parser::Url>(url) {
+                    path.extend(e.path().iter().cloned());
+                    message = e.inner().to_string();
+                }
+            }
+        }
+
+        if let Some(field) = missing_field(&message) {
+            // Already reported, it went missing when its bad value was removed
+            let mut field_path = path.clone();
+            field_path.push(Segment::Map {
+                key: field.to_string(),
+            });
+            if !removed.contains(&display_path(&field_path)) {
+                problems.push((path, message));
+            }
+            break;
+        }
+
+        if let Some(s) = suggestion(&message) {
+            message = format!("{message}, did you mean `{s}`?");
+        }
+        let display = display_path(&path);
+        let message = if display.is_empty() {
+            message
+        } else {
+            format!("{display}: {message}")
+        };
+        let removable = remove(value, &path);
+        problems.push((path, message));
+        if !removable {
+            break;
+        }
+        removed.push(display);
+    }
+    problems
+}
+
+fn missing_field(message: &str) -> Option<&str> {
+    message
+        .strip_prefix("missing field `")
+        .and_then(|m| m.split('`').next())
+}
+
+/// The kind and name from an "unknown field `x`" or "unknown variant `x`"
+/// error.
+fn unknown_name(message: &str) -> Option<(&str, &str)> {
+    let rest = message.strip_prefix("unknown ")?;
+    let (kind, rest) = rest.split_once(" `")?;
+    Some((kind, rest.split('`').next()?))
+}
+
+/// Suggest the closest of the names serde expected for an unknown field or
+/// variant.
+fn suggestion(message: &str) -> Option<String> {
+    let (_, name) = unknown_name(message)?;
+    let (_, expected) = message.split_once("expected")?;
+    expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (strsim::jaro_winkler(name, candidate), candidate))
+        .filter(|(score, _)| *score >= 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+fn display_path(path: &[Segment]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            Segment::Map { key } => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(key);
+            }
+            Segment::Seq { index } => out.push_str(&format!("[{index}]")),
+            Segment::Enum { .. } | Segment::Unknown => {}
+        }
+    }
+    out
+}
+
+fn get<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, segment| match segment {
+        Segment::Map { key } => v.get(key),
+        Segment::Seq { index } => v.get(index),
+        Segment::Enum { .. } | Segment::Unknown => Some(v),
+    })
+}
+
+fn remove(value: &mut Value, path: &[Segment]) -> bool {
+    let Some((last, parent)) = path.split_last() else {
+        return false;
+    };
+    let mut v = value;
+    for segment in parent {
+        v = match segment {
+            Segment::Map { key } => match v.get_mut(key) {
+                Some(next) => next,
+                None => return false,
+            },
+            Segment::Seq { index } => match v.get_mut(index) {
+                Some(next) => next,
+                None => return false,
+            },
+            Segment::Enum { .. } | Segment::Unknown => v,
+        };
+    }
+    match (last, v) {
+        (Segment::Map { key }, Value::Object(map)) => map.shift_remove(key).is_some(),
+        // keep the indexes of later elements stable
+        (Segment::Seq { index }, Value::Array(items)) if *index < items.len() => {
+            items[*index] = Value::Null;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Best effort line of the value at `path`: each key is searched for after
+/// the position of its parent, so nested keys with common names resolve to
+/// the right line as long as the file is laid out in order.
+pub fn locate(src: &str, path: &[Segment]) -> Option<usize> {
+    let mut offset = 0;
+    let mut found = false;
+    for segment in path {
+        if let Segment::Map { key } = segment {
+            offset = find_key(src, key, offset)?;
+            found = true;
+        }
+    }
+    found.then(|| src[..offset].matches('\n').count() + 1)
+}
+
+fn find_key(src: &str, key: &str, from: usize) -> Option<usize> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '$';
+    let mut start = from;
+    while let Some(i) = src[start..].find(key) {
+        let at = start + i;
+        let end = at + key.len();
+        let before = src[..at].chars().next_back();
+        let after = src[end..]
+            .trim_start_matches(['"', '\''])
+            .trim_start_matches([' ', '\t'])
+            .chars()
+            .next();
+        if !before.is_some_and(is_name) && matches!(after, Some(':' | '=' | ']' | '.')) {
+            return Some(at);
+        }
+        start = end;
+    }
+    None
+}
+
+/// Names of the `env_*`, `arg_*` and `vars.*` variables a template refers to.
+fn template_variables(template: &str) -> Vec<String> {
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    let mut vars = Vec::new();
+    for (i, _) in template.match_indices(['e', 'a', 'v']) {
+        let rest = &template[i..];
+        let Some(prefix) = ["env_", "arg_", "vars."]
+            .into_iter()
+            .find(|p| rest.starts_with(p))
+        else {
+            continue;
+        };
+        if template[..i].chars().next_back().is_some_and(is_name) {
+            continue;
+        }
+        let len = rest[prefix.len()..]
+            .find(|c: char| !is_name(c))
+            .unwrap_or(rest.len() - prefix.len());
+        let var = rest[..prefix.len() + len].to_string();
+        if len > 0 && !vars.contains(&var) {
+            vars.push(var);
+        }
+    }
+    vars
+}
+
+/// The JSON Schema for request files, for editors and other tooling.
+pub fn request_schema() -> String {
+    let schema = schemars::schema_for!(Request);
+    serde_json::to_string_pretty(&schema).expect("schema serializes") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(src: &str, format: RequestFormat) -> Vec<String> {
+        let dir = std::env::temp_dir().join(format!(
+            "rascal-validate-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("req");
+        std::fs::write(&file, src).unwrap();
+        let problems = validate_file(&file, Some(format), None, &HashMap::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        problems
+            .iter()
+            .map(|p| format!("{:?}: {}", p.line, p.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let src = r#"{
+            "$schema": "../schema/request.schema.json",
+            "method": "POST",
+            "url": "https://{{ env_RASCAL_UNSET_HOST }}/{{ arg_id }}",
+            "body": {"json": {"anything": ["goes", 1]}},
+            "auth": {"type": "Bearer", "token": "{{ arg_token }}"}
+        }"#;
+        assert_eq!(check(src, RequestFormat::Json), Vec::<String>::new());
+
+        // placeholders also fill slots outside of strings
+        let src = r#"{
+            "method": "GET",
+            "url": {"host": "{{ arg_host }}", "port": {{ arg_port }}},
+            "client": {"timeout": {{ vars.timeout }}},
+            "body": {"json": {{ arg_payload }}}
+        }"#;
+        assert_eq!(check(src, RequestFormat::Json), Vec::<String>::new());
+        let src = "method = \"GET\"\nurl = \"https://{{ arg_host }}\"\n\n[client]\ntimeout = {{ arg_timeout }}\n";
+        assert_eq!(check(src, RequestFormat::Toml), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let src = r#"{
+    "method": "GET",
+    "url": {"host": "example.com", "pth": "/a"},
+    "header": {"accept": "*/*"},
+    "body": {"raw": "x", "jsn": {}},
+    "auth": {"type": "Bearer", "tokn": "abc"},
+    "retry": {"max_attempts": "three"}
+}"#;
+        assert_eq!(
+            check(src, RequestFormat::Json),
+            vec![
+                "Some(3): url.pth: unknown field `pth`, expected one of `protocol`, `host`, \
+                 `port`, `path`, `params`, `fragment`, `username`, `password`, did you mean `path`?",
+                "Some(4): header: unknown field `header`, expected one of `$schema`, `extends`, \
+                 `version`, `method`, `url`, `params`, `headers`, `body`, `auth`, `tls`, `client`, \
+                 `retry`, did you mean `headers`?",
+                "Some(5): body.jsn: unknown field `jsn`, expected one of `raw`, `filepath`, \
+                 `json`, `multipart`, `form`, `base64`, did you mean `json`?",
+                // internally tagged enums lose the path below the enum itself
+                "Some(6): auth: unknown field `tokn`, expected `token`, did you mean `token`?",
+                "Some(7): retry.max_attempts: invalid type: string \"three\", expected u32",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_yaml_and_toml() {
+        let yaml = "method: GETT\nurl: https://example.com\nheaders:\n  accept: '*/*'\n";
+        assert_eq!(
+            check(yaml, RequestFormat::Yaml),
+            vec![
+                "Some(1): method: unknown variant `GETT`, expected one of `GET`, `HEAD`, `POST`, \
+                 `PUT`, `DELETE`, `CONNECT`, `OPTIONS`, `TRACE`, `PATCH`, did you mean `GET`?"
+            ]
+        );
+        assert_eq!(
+            check("method: [\n", RequestFormat::Yaml)[0]
+                .split(':')
+                .next(),
+            Some("Some(2)")
+        );
+
+        let toml = "method = \"GET\"\nurl = \"https://example.com\"\n\n[client]\ntimout = 1.0\n";
+        assert_eq!(
+            check(toml, RequestFormat::Toml),
+            vec![
+                "Some(5): client.timout: unknown field `timout`, expected one of \
+                 `connect_timeout`, `timeout`, `max_redirects`, `proxy`, did you mean `timeout`?"
+            ]
+        );
+        assert_eq!(
+            check("method = \n", RequestFormat::Toml)[0]
+                .split(':')
+                .next(),
+            Some("Some(1)")
+        );
+    }
+
+    #[test]
+    fn test_suggestion() {
+        assert_eq!(
+            suggestion("unknown field `hedaers`, expected `headers` or `body`"),
+            Some("headers".to_string())
+        );
+        assert_eq!(
+            suggestion("unknown field `zzz`, expected `headers` or `body`"),
+            None
+        );
+        assert_eq!(suggestion("invalid type: string"), None);
+    }
+
+    #[test]
+    fn test_validate_extends() {
+        let dir =
+            std::env::temp_dir().join(format!("rascal-validate-extends-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("base.yaml"),
+            "url: https://{{ env_RASCAL_UNSET_HOST }}\nheaders:\n  accept: '*/*'\nretry:\n  max_atempts: 2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("req.json"),
+            "{\n  \"extends\": \"base.yaml\",\n  \"method\": \"GET\"\n}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("bad.json"),
+            "{\n  \"method\": \"GET\",\n  \"extends\": \"nope.json\"\n}",
+        )
+        .unwrap();
+        let messages = |file: &str| -> Vec<String> {
+            validate_file(&dir.join(file), None, None, &HashMap::new())
+                .iter()
+                .map(|p| format!("{:?}: {}", p.line, p.message))
+                .collect()
+        };
+        let req = messages("req.json");
+        let bad = messages("bad.json");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            req,
+            vec![
+                "None: retry.max_atempts: unknown field `max_atempts`, expected one of \
+                 `max_attempts`, `statuses`, `errors`, `initial_delay`, `max_delay`, \
+                 `multiplier`, `jitter`, `respect_retry_after`, `non_idempotent`, \
+                 did you mean `max_attempts`?"
+            ]
+        );
+        assert_eq!(bad.len(), 1);
+        assert!(
+            bad[0].starts_with("Some(3): failed to resolve extends in file="),
+            "{}",
+            bad[0]
+        );
+    }
+
+    #[test]
+    fn test_validate_collection() {
+        let dir =
+            std::env::temp_dir().join(format!("rascal-validate-collection-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("users")).unwrap();
+        std::fs::write(
+            dir.join("rascal.toml"),
+            "base_url = \"https://example.com\"\n[client]\ntimeout = \"slow\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("users/get.json"),
+            r#"{"method": "GET", "url": "/users"}"#,
+        )
+        .unwrap();
+        let files = request_files(&[dir.display().to_string()]).unwrap();
+        let problems = validate_file(&dir.join("users/get.json"), None, None, &HashMap::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("users/get.json")]);
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0]
+                .message
+                .starts_with("invalid collection config in file="),
+            "{}",
+            problems[0]
+        );
+        assert!(problems[0]
+            .message
+            .ends_with("invalid type: string \"slow\", expected f64"));
+    }
+
+    #[test]
+    fn test_template_variables() {
+        assert_eq!(
+            template_variables(
+                "{{ env_HOST }}/{{arg_id}}/{{ parg_x }}{{ env_HOST | upper }}{{ vars.user_id }}"
+            ),
+            vec!["env_HOST", "arg_id", "vars.user_id"]
+        );
+    }
+
+    #[test]
+    fn test_request_schema_is_up_to_date() {
+        // regenerate with `cargo run -- schema > schema/request.schema.json`
+        let path = format!("{}/schema/request.schema.json", env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), request_schema());
+    }
+}